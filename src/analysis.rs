@@ -0,0 +1,183 @@
+use std::{collections::HashSet, fmt::{self, Display}, path::Path};
+
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumIter};
+
+use crate::weather::WeatherTag;
+
+/* Size images are downscaled to before analysis */
+const SAMPLE_SIZE: u32 = 64;
+
+/* Mean luminance at which an image is equally likely to be day or night */
+const NEUTRAL_LUMINANCE: f32 = 0.3;
+
+/* Pixel classification thresholds (saturation and value from 0 to 1, hue in degrees) */
+const WHITE_SATURATION: f32 = 0.12;
+const WHITE_VALUE: f32 = 0.85;
+const GREY_SATURATION: f32 = 0.15;
+const GREY_MIN_VALUE: f32 = 0.25;
+const BLUE_HUES: std::ops::RangeInclusive<f32> = 190.0..=250.0;
+const BLUE_SATURATION: f32 = 0.35;
+const BLUE_MIN_VALUE: f32 = 0.35;
+
+/* Proportion of pixels needed to suggest each tag */
+const SNOW_FRACTION: f32 = 0.35;
+const OVERCAST_FRACTION: f32 = 0.4;
+const CLEAR_SKY_FRACTION: f32 = 0.25;
+const PART_CLOUD_FRACTION: f32 = 0.2;
+
+/* Luminance deviation below which an overcast image is considered foggy */
+const FOG_CONTRAST: f32 = 0.12;
+
+
+/* Whether to analyse new wallpapers for day/night */
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Display, EnumIter)]
+pub enum Detection {
+    Off,
+    /* Save suggestions to review in the tag editor */
+    #[default]
+    Suggest,
+    /* Apply suggestions above the confidence threshold, saving the rest to review */
+    Apply,
+}
+
+
+/* Day/night suggested by analysing an image */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Suggestion {
+    is_day: bool,
+    confidence: f32, /* 0 (no idea) to 1 (certain) */
+}
+
+/* Print suggested day/night and confidence */
+impl Display for Suggestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:.0}% confidence)",
+            if self.is_day { "day" } else { "night" },
+            self.confidence * 100.0
+        )
+    }
+}
+
+impl Suggestion {
+    pub fn is_day(&self) -> bool {
+        self.is_day
+    }
+
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+}
+
+
+/* Results of analysing an image */
+pub struct Analysis {
+    pub day_night: Suggestion,
+    pub tags: HashSet<WeatherTag>,
+}
+
+/* Analyse downscaled image for day/night and weather depicted */
+pub fn analyse(file_path: &Path) -> Option<Analysis> {
+    let image = image::open(file_path).ok()?
+        .thumbnail(SAMPLE_SIZE, SAMPLE_SIZE)
+        .to_rgb8();
+
+    let pixels: Vec<[f32; 3]> = image.pixels()
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]].map(|channel| channel as f32 / 255.0))
+        .collect();
+
+    if pixels.is_empty() {
+        return None;
+    }
+
+    Some(Analysis {
+        day_night: suggest_day_night(&pixels),
+        tags: suggest_tags(&pixels),
+    })
+}
+
+/* Suggest day/night from mean luminance and colour temperature */
+fn suggest_day_night(pixels: &[[f32; 3]]) -> Suggestion {
+    let count = pixels.len() as f32;
+
+    let [red, green, blue] = pixels.iter()
+        .fold([0.0; 3], |sum, pixel| [sum[0] + pixel[0], sum[1] + pixel[1], sum[2] + pixel[2]])
+        .map(|sum| sum / count);
+
+    /* Relative luminance (Rec. 709) */
+    let luminance = 0.2126 * red + 0.7152 * green + 0.0722 * blue;
+
+    /* Warm (red) casts suggest daylight, cool (blue) casts suggest night (less so in bright images, e.g. blue skies) */
+    let warmth = (red - blue) * (1.0 - luminance);
+
+    let score = (luminance - NEUTRAL_LUMINANCE) * 3.0 + warmth * 0.3;
+
+    Suggestion {
+        is_day: score > 0.0,
+        confidence: score.abs().min(1.0),
+    }
+}
+
+/* Suggest weather tags from the proportions of grey, white and blue pixels and the image's contrast */
+fn suggest_tags(pixels: &[[f32; 3]]) -> HashSet<WeatherTag> {
+    let count = pixels.len() as f32;
+    let fraction = |matches: &dyn Fn(f32, f32, f32) -> bool| pixels.iter()
+        .filter(|pixel| {
+            let (hue, saturation, value) = hsv(pixel);
+            matches(hue, saturation, value)
+        })
+        .count() as f32 / count;
+
+    let white = fraction(&|_, s, v| s < WHITE_SATURATION && v > WHITE_VALUE);
+    let grey = fraction(&|_, s, v| s < GREY_SATURATION && (GREY_MIN_VALUE..=WHITE_VALUE).contains(&v));
+    let blue = fraction(&|h, s, v| BLUE_HUES.contains(&h) && s > BLUE_SATURATION && v > BLUE_MIN_VALUE);
+
+    let contrast = luminance_deviation(pixels);
+
+    let mut tags = HashSet::new();
+
+    if white >= SNOW_FRACTION {
+        tags.insert(WeatherTag::Snow);
+    }
+
+    if grey >= OVERCAST_FRACTION {
+        tags.insert(if contrast < FOG_CONTRAST { WeatherTag::Fog } else { WeatherTag::Cloud });
+    } else if blue >= CLEAR_SKY_FRACTION {
+        tags.insert(if grey >= PART_CLOUD_FRACTION { WeatherTag::PartCloud } else { WeatherTag::Sun });
+    }
+
+    tags
+}
+
+/* Convert RGB to hue (degrees), saturation and value */
+fn hsv(&[red, green, blue]: &[f32; 3]) -> (f32, f32, f32) {
+    let max = red.max(green).max(blue);
+    let min = red.min(green).min(blue);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == red {
+        60.0 * ((green - blue) / delta).rem_euclid(6.0)
+    } else if max == green {
+        60.0 * ((blue - red) / delta + 2.0)
+    } else {
+        60.0 * ((red - green) / delta + 4.0)
+    };
+
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    (hue, saturation, max)
+}
+
+/* Standard deviation of luminance */
+fn luminance_deviation(pixels: &[[f32; 3]]) -> f32 {
+    let luminances: Vec<f32> = pixels.iter()
+        .map(|[red, green, blue]| 0.2126 * red + 0.7152 * green + 0.0722 * blue)
+        .collect();
+
+    let count = luminances.len() as f32;
+    let mean = luminances.iter().sum::<f32>() / count;
+
+    (luminances.iter().map(|l| (l - mean).powi(2)).sum::<f32>() / count).sqrt()
+}
@@ -0,0 +1,88 @@
+use std::{cmp::Reverse, fs, io, path::{Path, PathBuf}};
+
+use chrono::{Local, NaiveDateTime};
+
+use crate::files;
+
+const BACKUPS_DIR: &str = "backups";
+
+/* Number of backups kept of each file, oldest deleted first */
+const BACKUP_COUNT: usize = 10;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+
+/* Timestamped copy of a saved file */
+pub struct Backup {
+    path: PathBuf,
+    created: NaiveDateTime,
+}
+
+impl Backup {
+    pub fn created(&self) -> NaiveDateTime {
+        self.created
+    }
+}
+
+
+/* Copy file into the backups directory, deleting its oldest backups beyond the limit */
+pub fn back_up(file_path: &Path) -> io::Result<()> {
+    let (stem, ext) = stem_and_extension(file_path);
+    let backup = backups_path()?.join(format!("{stem}-{}.{ext}", Local::now().format(TIMESTAMP_FORMAT)));
+
+    fs::copy(file_path, backup)?;
+
+    for old in list_backups(file_path)?.iter().skip(BACKUP_COUNT) {
+        fs::remove_file(&old.path)?;
+    }
+
+    Ok(())
+}
+
+/* List backups of file, newest first */
+pub fn list_backups(file_path: &Path) -> io::Result<Vec<Backup>> {
+    let (stem, ext) = stem_and_extension(file_path);
+    let prefix = format!("{stem}-");
+    let suffix = format!(".{ext}");
+
+    let mut backups: Vec<Backup> = fs::read_dir(backups_path()?)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let timestamp = name.strip_prefix(&prefix)?.strip_suffix(&suffix)?;
+
+            Some(Backup {
+                created: NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?,
+                path: entry.path(),
+            })
+        })
+        .collect();
+
+    backups.sort_by_key(|backup| Reverse(backup.created));
+    Ok(backups)
+}
+
+/* Replace file with backup (backing up the current contents first) */
+pub fn restore(backup: &Backup, file_path: &Path) -> io::Result<()> {
+    let contents = fs::read(&backup.path)?;
+
+    if file_path.exists() {
+        back_up(file_path)?;
+    }
+
+    files::write_atomic(file_path, contents)
+}
+
+fn stem_and_extension(file_path: &Path) -> (String, String) {
+    let part = |part: Option<&std::ffi::OsStr>| part.map_or(String::new(), |part| part.to_string_lossy().into_owned());
+
+    (part(file_path.file_stem()), part(file_path.extension()))
+}
+
+/* Get backups directory (nested in data directory) */
+fn backups_path() -> io::Result<PathBuf> {
+    let backups_dir = files::data_path()?.join(BACKUPS_DIR);
+    fs::create_dir_all(&backups_dir)?;
+
+    Ok(backups_dir)
+}
@@ -0,0 +1,260 @@
+use std::{collections::{BTreeSet, HashSet}, fmt::{self, Display}, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumIter};
+
+use crate::{files, rating::Rating, settings::Config, storage, wallpaper::{self, Wallpaper}, weather::{self, DayNight, WeatherTag}};
+
+/* Current version of the JSON bundle format */
+const BUNDLE_VERSION: u32 = 1;
+
+/* Separator between tags and collections in CSV cells */
+const LIST_SEPARATOR: &str = ";";
+
+
+/* How imported tags are combined with existing tags */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumIter)]
+pub enum ImportMode {
+    /* Add imported tags and collections, keeping existing day/night and rating (unless untagged) */
+    Merge,
+    /* Replace tags, day/night, rating and collections with the imported ones */
+    Overwrite,
+}
+
+
+/* Machine-independent tags of a wallpaper, matched by content hash (or file name) on import */
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    filename: String,
+    #[serde(default)]
+    hash: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>, /* Tag names or keywords */
+    time: DayNight,
+    #[serde(default)]
+    rating: Rating,
+    #[serde(default)]
+    collections: Vec<String>,
+}
+
+/* Entry as a CSV row, with lists in single cells */
+#[derive(Debug, Serialize, Deserialize)]
+struct Row {
+    filename: String,
+    hash: String,
+    tags: String,
+    time: DayNight,
+    rating: Rating,
+    collections: String,
+}
+
+/* Portable JSON bundle of tags and collections */
+#[derive(Debug, Serialize, Deserialize)]
+struct Bundle {
+    version: u32,
+    #[serde(default)]
+    collections: Vec<String>,
+    wallpapers: Vec<Entry>,
+}
+
+impl Entry {
+    fn from_wallpaper(wallpaper: &Wallpaper) -> Entry {
+        Entry {
+            filename: wallpaper::filename(wallpaper.as_ref()),
+            hash: wallpaper.hash().map(String::from),
+            tags: weather::tag_names(wallpaper.weather.tags()),
+            time: DayNight::from_is_day(wallpaper.weather.is_day()),
+            rating: wallpaper.rating(),
+            collections: wallpaper.collections().iter().cloned().collect(),
+        }
+    }
+
+    fn tags(&self) -> HashSet<WeatherTag> {
+        self.tags.iter()
+            .flat_map(|keyword| WeatherTag::from_keyword(keyword))
+            .collect()
+    }
+
+    fn apply(&self, wallpaper: &mut Wallpaper, mode: ImportMode) {
+        let untagged = wallpaper.weather.tags().is_empty();
+
+        match mode {
+            ImportMode::Merge => {
+                let mut tags = wallpaper.weather.tags().clone();
+                tags.extend(self.tags());
+                wallpaper.weather.set_tags(tags);
+
+                let mut collections = wallpaper.collections().clone();
+                collections.extend(self.collections.iter().cloned());
+                wallpaper.set_collections(collections);
+            },
+            ImportMode::Overwrite => {
+                wallpaper.weather.set_tags(self.tags());
+                wallpaper.set_collections(self.collections.iter().cloned().collect());
+            },
+        }
+
+        if mode == ImportMode::Overwrite || untagged {
+            wallpaper.weather.set_is_day(self.time.is_day());
+            wallpaper.clear_suggestion();
+            wallpaper.set_rating(self.rating);
+        }
+
+        wallpaper.set_suggested_tags(HashSet::new());
+    }
+}
+
+impl From<Entry> for Row {
+    fn from(entry: Entry) -> Row {
+        Row {
+            filename: entry.filename,
+            hash: entry.hash.unwrap_or_default(),
+            tags: entry.tags.join(LIST_SEPARATOR),
+            time: entry.time,
+            rating: entry.rating,
+            collections: entry.collections.join(LIST_SEPARATOR),
+        }
+    }
+}
+
+impl From<Row> for Entry {
+    fn from(row: Row) -> Entry {
+        let split = |cell: &str| cell.split(LIST_SEPARATOR)
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect();
+
+        Entry {
+            filename: row.filename,
+            hash: (!row.hash.is_empty()).then_some(row.hash),
+            tags: split(&row.tags),
+            time: row.time,
+            rating: row.rating,
+            collections: split(&row.collections),
+        }
+    }
+}
+
+
+/* Wallpapers matched and not matched by an import */
+pub struct ImportSummary {
+    updated: usize,
+    unmatched: usize,
+}
+
+impl Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Updated {} wallpapers ({} imported entries did not match any wallpaper)", self.updated, self.unmatched)
+    }
+}
+
+
+/* Export tags of all wallpapers as CSV (if the path ends in .csv) or a JSON bundle, returning how many were exported */
+pub fn export(config: &Config, path: &Path) -> io::Result<usize> {
+    let mut wallpapers: Vec<Wallpaper> = files::load_all_wallpapers(config)?.into_iter().collect();
+    wallpapers.sort();
+
+    let entries: Vec<Entry> = wallpapers.iter().map(Entry::from_wallpaper).collect();
+    let count = entries.len();
+
+    let contents = if is_csv(path) {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+
+        for entry in entries {
+            writer.serialize(Row::from(entry)).map_err(csv_error)?;
+        }
+
+        writer.into_inner().map_err(|err| io::Error::other(err.to_string()))?
+    } else {
+        let bundle = Bundle {
+            version: BUNDLE_VERSION,
+            collections: storage::open(config)?.load_collections().unwrap_or_default(),
+            wallpapers: entries,
+        };
+
+        serde_json::to_vec_pretty(&bundle)?
+    };
+
+    files::write_atomic(path, contents)?;
+    Ok(count)
+}
+
+/* Import tags from CSV or a JSON bundle, applying each entry to wallpapers with the same contents (or the only one with its file name) */
+pub fn import(config: &Config, path: &Path, mode: ImportMode) -> io::Result<ImportSummary> {
+    let (entries, collections) = if is_csv(path) {
+        let entries = csv::Reader::from_path(path)
+            .map_err(csv_error)?
+            .deserialize::<Row>()
+            .map(|row| row.map(Entry::from))
+            .collect::<Result<Vec<Entry>, _>>()
+            .map_err(csv_error)?;
+
+        (entries, Vec::new())
+    } else {
+        let bundle: Bundle = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        if bundle.version > BUNDLE_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Bundle was exported by a newer version"));
+        }
+
+        (bundle.wallpapers, bundle.collections)
+    };
+
+    let mut wallpapers: Vec<Wallpaper> = files::load_all_wallpapers(config)?.into_iter().collect();
+    let mut updated = BTreeSet::new();
+    let mut unmatched = 0;
+
+    for entry in &entries {
+        let matches = matching(&wallpapers, entry);
+
+        if matches.is_empty() {
+            unmatched += 1;
+        }
+
+        for index in matches {
+            entry.apply(&mut wallpapers[index], mode);
+            updated.insert(index);
+        }
+    }
+
+    /* Keep collection names (including any only used by imported wallpapers) */
+    let mut store = storage::open(config)?;
+    let mut names = store.load_collections().unwrap_or_default();
+
+    for name in collections.into_iter().chain(entries.into_iter().flat_map(|entry| entry.collections)) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    store.save_collections(&names)?;
+    files::save_all_wallpapers(&wallpapers.into_iter().collect(), config)?;
+
+    Ok(ImportSummary { updated: updated.len(), unmatched })
+}
+
+/* Indices of wallpapers with the entry's hash, or else of the only wallpaper with its file name */
+fn matching(wallpapers: &[Wallpaper], entry: &Entry) -> Vec<usize> {
+    let by_hash: Vec<usize> = (0..wallpapers.len())
+        .filter(|&i| entry.hash.is_some() && wallpapers[i].hash() == entry.hash.as_deref())
+        .collect();
+
+    if !by_hash.is_empty() {
+        return by_hash;
+    }
+
+    let by_name: Vec<usize> = (0..wallpapers.len())
+        .filter(|&i| wallpaper::filename(wallpapers[i].as_ref()) == entry.filename)
+        .collect();
+
+    if by_name.len() == 1 { by_name } else { Vec::new() }
+}
+
+fn is_csv(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+}
+
+fn csv_error(err: csv::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -0,0 +1,400 @@
+use std::{collections::HashSet, fs, io, path::{Path, PathBuf}, process::ExitCode};
+
+use clap::{Args, Parser, Subcommand};
+use serde_json::Value;
+
+use crate::{bundle::{self, ImportMode}, control, daemon, files, library, rating::Rating, schedule, settings::{self, Config}, start, storage, wallpaper::Wallpaper, weather::{self, DayNight, Weather, WeatherTag}, Error};
+
+
+/* Command line arguments (the interactive menu is shown when no command is given) */
+#[derive(Parser)]
+#[command(name = "weather_wallpaper", version, about = "Set wallpapers to match the current weather")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(about = "Keep setting wallpapers to match the weather")]
+    Run {
+        #[arg(long, help = "Only choose wallpapers in this collection")]
+        collection: Option<String>,
+    },
+    #[command(about = "Keep setting wallpapers in the background, logging JSON lines instead of drawing to the terminal")]
+    Daemon {
+        #[arg(long, help = "Only choose wallpapers in this collection")]
+        collection: Option<String>,
+        #[arg(long, help = "Append logs to this file instead of stderr")]
+        log_file: Option<PathBuf>,
+    },
+    #[command(about = "Send a command to the running instance and print its JSON response")]
+    Control {
+        #[command(subcommand)]
+        command: control::Command,
+    },
+    #[command(about = "Set one wallpaper for the current weather, print its path and exit")]
+    Once {
+        #[arg(long, help = "Only choose wallpapers in this collection")]
+        collection: Option<String>,
+    },
+    #[command(about = "Set the tags, day/night or rating of a wallpaper")]
+    Tag(TagArgs),
+    #[command(about = "Print the paths of wallpapers, one per line")]
+    List {
+        #[arg(long, help = "Only list wallpapers with this weather tag")]
+        tag: Option<String>,
+        #[arg(long, help = "Only list wallpapers in this collection")]
+        collection: Option<String>,
+    },
+    #[command(about = "Print the current weather")]
+    Weather {
+        #[arg(long, help = "Print as JSON")]
+        json: bool,
+    },
+    #[command(about = "Read or change settings")]
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    #[command(about = "Export tags as CSV (.csv) or a JSON bundle")]
+    Export {
+        path: PathBuf,
+    },
+    #[command(about = "Import tags from CSV (.csv) or a JSON bundle")]
+    Import {
+        path: PathBuf,
+        #[arg(long, help = "Replace existing tags, day/night, ratings and collections instead of merging")]
+        overwrite: bool,
+    },
+}
+
+#[derive(Args)]
+pub struct TagArgs {
+    file: PathBuf,
+    #[arg(long, value_delimiter = ',', help = "Comma-separated tags or keywords, e.g. rain,cloud")]
+    tags: Option<Vec<String>>,
+    #[arg(long, requires = "tags", help = "Add to the existing tags instead of replacing them")]
+    add: bool,
+    #[arg(long, group = "time", help = "Only choose during the day")]
+    day: bool,
+    #[arg(long, group = "time", help = "Only choose at night")]
+    night: bool,
+    #[arg(long, group = "time", help = "Choose during the day and at night")]
+    both: bool,
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=crate::rating::MAX_RATING as i64), help = "Star rating")]
+    rating: Option<u8>,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    #[command(about = "Print a setting (or all settings) as JSON")]
+    Get {
+        key: Option<String>,
+    },
+    #[command(about = "Change a setting, given as JSON (plain words are read as strings)")]
+    Set {
+        key: String,
+        value: String,
+    },
+}
+
+
+/* Exit codes of commands, so scripts can tell failures apart */
+#[derive(Debug, Clone, Copy)]
+pub enum Status {
+    Success = 0,
+    Failure = 1,
+    Usage = 2, /* Invalid arguments (also used by clap for unparseable ones) */
+    NotFound = 3, /* Unknown wallpaper, collection or setting */
+    NoWallpapers = 4,
+    WeatherFetchFail = 5,
+    AlreadyRunning = 6, /* Another daemon holds the PID file */
+    NotRunning = 7, /* No instance is listening for control commands */
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> ExitCode {
+        ExitCode::from(status as u8)
+    }
+}
+
+
+/* Run command, printing errors to stderr */
+pub fn run(command: Command, loaded: io::Result<Config>) -> Status {
+    let loaded = saved_settings(loaded);
+    let config = &loaded.as_ref().cloned().unwrap_or_default();
+
+    let result = match command {
+        Command::Run { collection } => run_loop(config, collection.as_deref()),
+        Command::Daemon { collection, log_file } => run_daemon(config, collection.as_deref(), log_file.as_deref()),
+        Command::Control { command } => send_command(&command),
+        Command::Once { collection } => once(config, collection.as_deref()),
+        Command::Tag(args) => tag(config, args),
+        Command::List { tag, collection } => list(config, tag.as_deref(), collection.as_deref()),
+        Command::Weather { json } => print_weather(json),
+        /* Settings are written back, so unreadable settings must not be replaced by the defaults */
+        Command::Config { action } => loaded.and_then(|mut config| edit_config(&mut config, action)),
+        Command::Export { path } => export(config, &path),
+        Command::Import { path, overwrite } => import(config, &path, overwrite),
+    };
+
+    match result {
+        Ok(()) => Status::Success,
+        Err((status, message)) => {
+            eprintln!("{message}");
+            status
+        },
+    }
+}
+
+type CommandResult = Result<(), (Status, String)>;
+
+fn failure(err: impl ToString) -> (Status, String) {
+    (Status::Failure, err.to_string())
+}
+
+/* Loaded settings, or the defaults if none have been saved yet */
+fn saved_settings(loaded: io::Result<Config>) -> Result<Config, (Status, String)> {
+    match loaded {
+        Ok(config) => Ok(config),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(err) => Err(failure(format!("Could not load settings: {err}"))),
+    }
+}
+
+
+fn run_loop(config: &Config, collection: Option<&str>) -> CommandResult {
+    check_collection(config, collection)?;
+    start(config, collection).map_err(instance_error)
+}
+
+fn run_daemon(config: &Config, collection: Option<&str>, log_file: Option<&Path>) -> CommandResult {
+    check_collection(config, collection)?;
+    daemon::run(config.clone(), collection, log_file).map_err(instance_error)
+}
+
+/* Error starting an instance, which fails with WouldBlock if another is running */
+fn instance_error(err: io::Error) -> (Status, String) {
+    match err.kind() {
+        io::ErrorKind::WouldBlock => (Status::AlreadyRunning, err.to_string()),
+        _ => failure(err),
+    }
+}
+
+fn send_command(command: &control::Command) -> CommandResult {
+    let response = control::send(command).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => (Status::NotRunning, String::from("No running instance")),
+        _ => failure(err),
+    })?;
+
+    println!("{}", serde_json::to_string_pretty(&response).map_err(failure)?);
+
+    if response.is_ok() {
+        Ok(())
+    } else {
+        Err((Status::Failure, String::from("Command failed")))
+    }
+}
+
+fn once(config: &Config, collection: Option<&str>) -> CommandResult {
+    check_collection(config, collection)?;
+
+    let wallpapers = library::load_collection(config, collection).map_err(failure)?;
+    let allowed: HashSet<&Wallpaper> = crate::enabled(&schedule::allowed(schedule::active_rule(config), &wallpapers), config);
+
+    if allowed.is_empty() {
+        return Err((Status::NoWallpapers, String::from("No wallpapers found")));
+    }
+
+    /* Fall back to a random wallpaper without weather, like the refresh loop */
+    let curr_weather: Option<Weather> = weather::get_current_weather().ok();
+    let chosen: &Wallpaper = crate::pick_wallpaper(curr_weather.as_ref(), &allowed, config);
+
+    chosen.set().map_err(failure)?;
+    storage::open(config).and_then(|mut store| store.record_selection(chosen, curr_weather.as_ref())).map_err(failure)?;
+
+    println!("{}", chosen.as_ref().display());
+    Ok(())
+}
+
+fn tag(config: &Config, args: TagArgs) -> CommandResult {
+    let file = fs::canonicalize(&args.file)
+        .map_err(|err| (Status::NotFound, format!("{}: {err}", args.file.display())))?;
+
+    let mut wallpapers = files::load_all_wallpapers(config).map_err(failure)?;
+
+    let Some(mut wallpaper) = wallpapers.iter()
+        .find(|wallpaper| fs::canonicalize(wallpaper).is_ok_and(|path| path == file))
+        .cloned() else {
+        return Err((Status::NotFound, format!("{} is not in any wallpaper source", file.display())));
+    };
+
+    if let Some(keywords) = &args.tags {
+        let tags = parse_tags(keywords)?;
+
+        if args.add {
+            let mut existing = wallpaper.weather.tags().clone();
+            existing.extend(tags);
+            wallpaper.weather.set_tags(existing);
+        } else {
+            wallpaper.weather.set_tags(tags);
+        }
+
+        wallpaper.set_suggested_tags(HashSet::new());
+    }
+
+    let time = match (args.day, args.night, args.both) {
+        (true, _, _) => Some(DayNight::Day),
+        (_, true, _) => Some(DayNight::Night),
+        (_, _, true) => Some(DayNight::Both),
+        _ => None,
+    };
+
+    if let Some(time) = time {
+        wallpaper.weather.set_is_day(time.is_day());
+        wallpaper.clear_suggestion();
+    }
+
+    if let Some(stars) = args.rating {
+        wallpaper.set_rating(Rating::new(stars));
+    }
+
+    wallpapers.replace(wallpaper);
+    files::save_all_wallpapers(&wallpapers, config).map_err(failure)
+}
+
+fn list(config: &Config, tag: Option<&str>, collection: Option<&str>) -> CommandResult {
+    check_collection(config, collection)?;
+
+    let wallpapers = library::load_collection(config, collection).map_err(failure)?;
+    let mut paths: Vec<&Path> = wallpapers.iter().map(AsRef::as_ref).collect();
+
+    if let Some(tag) = tag {
+        let tags = parse_tags(&[tag.to_string()])?;
+
+        let store = storage::open(config).map_err(failure)?;
+        let mut tagged: HashSet<PathBuf> = HashSet::new();
+
+        for tag in &tags {
+            tagged.extend(store.tagged(tag).map_err(failure)?);
+        }
+
+        paths.retain(|path| tagged.contains(*path));
+    }
+
+    paths.sort();
+
+    for path in paths {
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
+
+fn print_weather(json: bool) -> CommandResult {
+    let curr_weather = weather::get_current_weather()
+        .map_err(|err| match err {
+            Error::WeatherFetchFail => (Status::WeatherFetchFail, String::from("No weather found")),
+            err => failure(err),
+        })?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&curr_weather).map_err(failure)?);
+    } else {
+        println!("{curr_weather}");
+    }
+
+    Ok(())
+}
+
+/* Read or change settings through their saved JSON keys */
+fn edit_config(config: &mut Config, action: ConfigAction) -> CommandResult {
+    let Value::Object(mut settings) = serde_json::to_value(&*config).map_err(failure)? else {
+        unreachable!("Settings serialize to an object")
+    };
+
+    match action {
+        ConfigAction::Get { key: None } => {
+            println!("{}", serde_json::to_string_pretty(&settings).map_err(failure)?);
+        },
+        ConfigAction::Get { key: Some(key) } => {
+            let value = settings.get(&key)
+                .ok_or_else(|| unknown_setting(&key))?;
+
+            println!("{}", serde_json::to_string_pretty(value).map_err(failure)?);
+        },
+        ConfigAction::Set { key, value } => {
+            if !settings.contains_key(&key) {
+                return Err(unknown_setting(&key));
+            }
+
+            let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+            settings.insert(key.clone(), value);
+
+            *config = serde_json::from_value(Value::Object(settings))
+                .map_err(|err| (Status::Usage, format!("Invalid value for {key}: {err}")))?;
+
+            settings::save_settings(config).map_err(failure)?;
+        },
+    }
+
+    Ok(())
+}
+
+fn export(config: &Config, path: &Path) -> CommandResult {
+    let count = bundle::export(config, path).map_err(failure)?;
+    println!("Exported tags of {count} wallpapers to {}", path.display());
+
+    Ok(())
+}
+
+fn import(config: &Config, path: &Path, overwrite: bool) -> CommandResult {
+    if !path.is_file() {
+        return Err((Status::NotFound, format!("{} does not exist", path.display())));
+    }
+
+    let mode = if overwrite { ImportMode::Overwrite } else { ImportMode::Merge };
+    let summary = bundle::import(config, path, mode).map_err(failure)?;
+    println!("{summary}");
+
+    Ok(())
+}
+
+
+/* Parse tag names or keywords, rejecting unknown ones */
+fn parse_tags(keywords: &[String]) -> Result<HashSet<WeatherTag>, (Status, String)> {
+    let mut tags = HashSet::new();
+
+    for keyword in keywords {
+        let matched = WeatherTag::from_keyword(keyword);
+
+        if matched.is_empty() {
+            return Err((Status::Usage, format!("Unknown weather tag: {keyword}")));
+        }
+
+        tags.extend(matched);
+    }
+
+    Ok(tags)
+}
+
+fn check_collection(config: &Config, collection: Option<&str>) -> CommandResult {
+    let Some(collection) = collection else {
+        return Ok(());
+    };
+
+    let collections = storage::open(config)
+        .and_then(|store| store.load_collections())
+        .unwrap_or_default();
+
+    if collections.iter().any(|name| name == collection) {
+        Ok(())
+    } else {
+        Err((Status::NotFound, format!("No collection named {collection}")))
+    }
+}
+
+fn unknown_setting(key: &str) -> (Status, String) {
+    (Status::NotFound, format!("No setting named {key}"))
+}
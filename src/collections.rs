@@ -0,0 +1,104 @@
+use std::{collections::BTreeSet, io, path::PathBuf};
+
+use console::Term;
+use dialoguer::{Input, MultiSelect, Select};
+
+use crate::{files, format_items, schema::{self, Migration}, settings::Config, storage, wallpaper::Wallpaper};
+
+const COLLECTIONS_FILE: &str = "collections.json";
+
+/* Migrations from each version of the saved collections file to the next */
+const MIGRATIONS: [Migration; 1] = [
+    |collections| collections, /* 0 -> 1: unversioned list of names needs no changes */
+];
+
+
+/* Select a collection to restrict wallpapers to (Some(None) for no restriction, None if cancelled) */
+pub fn select_collection(config: &Config, prompt: &str, unrestricted: &str) -> Option<Option<String>> {
+    let collections = storage::open(config)
+        .and_then(|store| store.load_collections())
+        .unwrap_or_default();
+
+    if collections.is_empty() {
+        return Some(None);
+    }
+
+    let mut items = vec![String::from(unrestricted)];
+    items.extend(collections.iter().cloned());
+
+    let choice = Select::new()
+        .with_prompt(prompt)
+        .items(&format_items(items))
+        .default(0)
+        .report(false)
+        .interact_opt()
+        .unwrap()?;
+
+    Some(choice.checked_sub(1).map(|index| collections[index].clone()))
+}
+
+/* Edit which collections a wallpaper belongs to */
+pub fn edit_membership(wallpaper: &mut Wallpaper, config: &Config) -> io::Result<()> {
+    let mut store = storage::open(config)?;
+    let mut collections = store.load_collections().unwrap_or_default();
+
+    let mut items: Vec<(String, bool)> = collections.iter()
+        .map(|name| (name.clone(), wallpaper.collections().contains(name)))
+        .collect();
+
+    items.push((String::from("+ New collection"), false));
+
+    let Some(input) = MultiSelect::new()
+        .with_prompt("Select collections")
+        .items_checked(&items)
+        .report(false)
+        .interact_opt()
+        .unwrap() else {
+        return Ok(());
+    };
+
+    let mut membership: BTreeSet<String> = input.iter()
+        .filter_map(|&i| collections.get(i).cloned())
+        .collect();
+
+    /* Create new collection */
+    if input.contains(&collections.len()) {
+        let name = Input::<String>::new()
+            .with_prompt("Collection name")
+            .interact_text()
+            .unwrap();
+
+        Term::stdout().clear_last_lines(1).unwrap();
+
+        let name = name.trim().to_string();
+        if !name.is_empty() {
+            if !collections.contains(&name) {
+                collections.push(name.clone());
+                store.save_collections(&collections)?;
+            }
+
+            membership.insert(name);
+        }
+    }
+
+    wallpaper.set_collections(membership);
+
+    Ok(())
+}
+
+
+/* Save names of all collections to versioned JSON file */
+pub fn save_collections(collections: &[String]) -> io::Result<()> {
+    schema::save(&collections_path()?, &collections, &MIGRATIONS)?;
+    Ok(())
+}
+
+/* Load names of all collections from versioned JSON file */
+pub fn load_collections() -> io::Result<Vec<String>> {
+    schema::load(&collections_path()?, &MIGRATIONS)
+}
+
+/* Helper function to get path to file of saved collections */
+fn collections_path() -> io::Result<PathBuf> {
+    files::data_path().map(|path| path.join(COLLECTIONS_FILE))
+}
@@ -0,0 +1,234 @@
+use std::{fs, io, path::PathBuf, sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, Sender}, Arc}};
+
+#[cfg(unix)]
+use std::{io::{BufRead, BufReader, Write}, os::unix::net::{UnixListener, UnixStream}, thread, time::Duration};
+
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+use crate::{files, lock::PidLock, weather::{DayNight, Weather}};
+
+const SOCKET_FILE: &str = "control.sock";
+
+/* How long a client waits for the refresh loop to handle its command (it may be fetching the weather) */
+#[cfg(unix)]
+const REPLY_TIMEOUT_SECS: u64 = 30;
+
+/* How long to wait for a connected client to send its command */
+#[cfg(unix)]
+const COMMAND_TIMEOUT_SECS: u64 = 5;
+
+
+/* Commands controlling a running instance, sent as JSON lines such as {"command": "next"} */
+#[derive(Debug, Clone, Subcommand, Serialize, Deserialize, Display)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Command {
+    #[command(about = "Replace the wallpaper now")]
+    Next,
+    #[command(about = "Go back to the previous wallpaper")]
+    Previous,
+    #[command(about = "Keep the current wallpaper until resumed")]
+    Pause,
+    #[command(about = "Resume replacing wallpapers, ending any snooze")]
+    Resume,
+    #[command(about = "Keep the current wallpaper for a number of minutes")]
+    Snooze {
+        #[arg(help = "Minutes to snooze for (0 ends a snooze)")]
+        minutes: u64,
+    },
+    #[command(about = "Give the current wallpaper the highest rating")]
+    FavouriteCurrent,
+    #[command(about = "Give the current wallpaper the highest rating, or its previous rating if it already has it")]
+    ToggleFavourite,
+    #[command(about = "Choose wallpapers for the given weather instead of the forecast (clears the override if empty)")]
+    SetOverrideWeather {
+        #[arg(long, value_delimiter = ',', help = "Comma-separated tags or keywords, e.g. rain,cloud")]
+        #[serde(default)]
+        tags: Vec<String>,
+        #[arg(long, value_enum)]
+        #[serde(default)]
+        time: Option<DayNight>,
+    },
+    #[command(about = "Print the current wallpaper, weather and why it is paused (if it is)")]
+    Status,
+    #[command(about = "Reload settings from disk")]
+    ReloadConfig,
+}
+
+/* Reply to a command, as a JSON line */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<StatusReport>,
+}
+
+impl Response {
+    pub fn ok() -> Response {
+        Response { ok: true, error: None, status: None }
+    }
+
+    pub fn error(error: impl ToString) -> Response {
+        Response { ok: false, error: Some(error.to_string()), status: None }
+    }
+
+    pub fn status(status: StatusReport) -> Response {
+        Response { ok: true, error: None, status: Some(status) }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.ok
+    }
+
+    pub fn error_message(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+/* State of a running instance */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub pid: u32,
+    pub collection: Option<String>,
+    pub current: Option<PathBuf>,
+    pub weather: Option<Weather>, /* Weather the current wallpaper was chosen for */
+    pub override_weather: Option<Weather>,
+    pub paused: bool,
+    pub pause_reason: Option<String>, /* E.g. "Snoozed until 14:30" or "Paused on battery" */
+}
+
+
+/* Command waiting to be handled by the refresh loop (from the control socket or a keypress) */
+pub struct Request {
+    command: Command,
+    reply: Sender<Response>,
+}
+
+impl Request {
+    /* Request for command, along with where its response will be sent */
+    pub fn new(command: Command) -> (Request, Receiver<Response>) {
+        let (reply, response) = mpsc::channel();
+        (Request { command, reply }, response)
+    }
+
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /* Send response to the client (which may have stopped waiting) */
+    pub fn respond(self, response: Response) {
+        let _ = self.reply.send(response);
+    }
+}
+
+
+/* Listening control socket, stopped and removed when dropped */
+pub struct Socket {
+    path: PathBuf,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Drop for Socket {
+    /* Stop the listening thread (waking it from waiting for a connection) before removing the socket */
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+
+        #[cfg(unix)]
+        let _ = UnixStream::connect(&self.path);
+
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+
+/* Channel commands are passed to the refresh loop through */
+pub fn channel() -> (Sender<Request>, Receiver<Request>) {
+    mpsc::channel()
+}
+
+/* Listen for commands on the control socket, passing them to sender.
+ * Requires the instance lock, so no other instance can be replacing the socket meanwhile;
+ * a socket left behind by a crashed instance is replaced */
+#[cfg(unix)]
+pub fn serve(sender: Sender<Request>, _lock: &PidLock) -> io::Result<Socket> {
+    let path = socket_path()?;
+
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+
+    let stopped = Arc::new(AtomicBool::new(false));
+    let stop = Arc::clone(&stopped);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+
+            /* Each client is handled on its own thread, so a slow one does not hold up the others.
+             * A failed connection only affects its client */
+            if let Ok(stream) = stream {
+                let sender = sender.clone();
+                thread::spawn(move || handle_connection(stream, &sender));
+            }
+        }
+    });
+
+    Ok(Socket { path, stopped })
+}
+
+#[cfg(not(unix))]
+pub fn serve(_sender: Sender<Request>, _lock: &PidLock) -> io::Result<Socket> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Control socket is only available on Unix"))
+}
+
+/* Send command to the running instance and wait for its response */
+#[cfg(unix)]
+pub fn send(command: &Command) -> io::Result<Response> {
+    let mut stream = UnixStream::connect(socket_path()?)?;
+    stream.set_read_timeout(Some(Duration::from_secs(REPLY_TIMEOUT_SECS)))?;
+
+    writeln!(stream, "{}", serde_json::to_string(command)?)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(not(unix))]
+pub fn send(_command: &Command) -> io::Result<Response> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Control socket is only available on Unix"))
+}
+
+/* Read one command from the client and write back the refresh loop's response */
+#[cfg(unix)]
+fn handle_connection(stream: UnixStream, sender: &Sender<Request>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(COMMAND_TIMEOUT_SECS)))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = match serde_json::from_str::<Command>(&line) {
+        Ok(command) => {
+            let (request, response) = Request::new(command);
+
+            match sender.send(request) {
+                Ok(()) => response.recv_timeout(Duration::from_secs(REPLY_TIMEOUT_SECS))
+                    .unwrap_or_else(|_| Response::error("Timed out waiting for a response")),
+                Err(_) => Response::error("Instance is shutting down"),
+            }
+        },
+        Err(err) => Response::error(format!("Invalid command: {err}")),
+    };
+
+    writeln!(&stream, "{}", serde_json::to_string(&response)?)
+}
+
+fn socket_path() -> io::Result<PathBuf> {
+    files::data_path().map(|path| path.join(SOCKET_FILE))
+}
@@ -0,0 +1,231 @@
+use std::{fs::{File, OpenOptions}, io::{self, Write}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, mpsc::Receiver, Arc}, thread, time::Duration};
+
+use chrono::Local;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{control::{self, Request}, library::LibraryChanges, lock::PidLock, session::{Outcome, Refresh, Session}, settings::Config, wallpaper::Wallpaper, warnings};
+
+/* How often to check for signals and file changes while waiting to refresh */
+const POLL_MILLIS: u64 = 250;
+
+
+/* Severity of a log entry */
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+/* Writes log entries as JSON lines to stderr, or appends them to a file */
+struct Logger {
+    file: Option<File>,
+}
+
+impl Logger {
+    fn new(log_file: Option<&Path>) -> io::Result<Logger> {
+        let file = match log_file {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+
+        Ok(Logger { file })
+    }
+
+    /* Write entry with its time, level, event name and any extra fields */
+    fn log(&mut self, level: Level, event: &str, fields: Value) {
+        let mut entry = json!({
+            "time": Local::now().to_rfc3339(),
+            "level": level,
+            "event": event,
+        });
+
+        if let (Value::Object(entry), Value::Object(fields)) = (&mut entry, fields) {
+            entry.extend(fields);
+        }
+
+        let line = format!("{entry}\n");
+
+        /* Nowhere left to report a failed log write to */
+        let _ = match &mut self.file {
+            Some(file) => file.write_all(line.as_bytes()),
+            None => io::stderr().write_all(line.as_bytes()),
+        };
+    }
+}
+
+
+/* Signals received since they were last handled */
+struct Signals {
+    terminate: Arc<AtomicBool>,
+    reload: Arc<AtomicBool>,
+}
+
+impl Signals {
+    /* Handle SIGTERM and SIGINT by stopping, and SIGHUP (on Unix) by reloading settings */
+    fn register() -> io::Result<Signals> {
+        let signals = Signals {
+            terminate: Arc::new(AtomicBool::new(false)),
+            reload: Arc::new(AtomicBool::new(false)),
+        };
+
+        signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&signals.terminate))?;
+        signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&signals.terminate))?;
+
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&signals.reload))?;
+
+        Ok(signals)
+    }
+
+    fn terminated(&self) -> bool {
+        self.terminate.load(Ordering::Relaxed)
+    }
+
+    /* Whether a reload was requested, clearing the request */
+    fn take_reload(&self) -> bool {
+        self.reload.swap(false, Ordering::Relaxed)
+    }
+
+    fn pending(&self) -> bool {
+        self.terminated() || self.reload.load(Ordering::Relaxed)
+    }
+}
+
+
+/* Set wallpapers to match the weather without a terminal until terminated (restricted to collection if given) */
+pub fn run(config: Config, collection: Option<&str>, log_file: Option<&Path>) -> io::Result<()> {
+    let lock = PidLock::acquire()?;
+    let signals = Signals::register()?;
+    let mut logger = Logger::new(log_file)?;
+
+    /* Warnings (e.g. unreadable files) are logged rather than printed */
+    warnings::collect();
+
+    logger.log(Level::Info, "started", json!({ "pid": std::process::id(), "collection": collection }));
+
+    /* Accept commands from other processes (e.g. window manager keybindings) */
+    let (sender, receiver) = control::channel();
+    let _socket = control::serve(sender, &lock)
+        .inspect_err(|err| logger.log(Level::Warn, "control_socket_unavailable", json!({ "error": err.to_string() })))
+        .ok();
+
+    let session = Session::new(config, collection);
+    log_warnings(&mut logger);
+    let mut session = session?;
+    log_watch_errors(&session, &mut logger);
+
+    while !signals.terminated() {
+        if signals.take_reload() {
+            match session.reload() {
+                Ok(()) => {
+                    logger.log(Level::Info, "config_reloaded", json!({}));
+                    log_watch_errors(&session, &mut logger);
+                },
+                Err(err) => logger.log(Level::Error, "config_reload_failed", json!({ "error": err.to_string() })),
+            }
+        }
+
+        log_refresh(session.refresh(), &mut logger);
+        log_warnings(&mut logger);
+        wait(&mut session, &receiver, &signals, &mut logger);
+    }
+
+    logger.log(Level::Info, "stopped", json!({}));
+    Ok(())
+}
+
+/* Log warnings collected since last logged */
+fn log_warnings(logger: &mut Logger) {
+    for warning in warnings::take() {
+        logger.log(Level::Warn, warning.event, json!({ "message": warning.message }));
+    }
+}
+
+/* Log each source that could not be watched for changes */
+fn log_watch_errors(session: &Session, logger: &mut Logger) {
+    for err in session.watch_errors() {
+        logger.log(Level::Warn, "watch_failed", json!({ "error": err }));
+    }
+}
+
+/* Log the weather a refresh used and what it did */
+fn log_refresh(refresh: Refresh, logger: &mut Logger) {
+    if refresh.weather.is_none() {
+        logger.log(Level::Warn, "weather_fetch_failed", json!({}));
+    }
+
+    let weather = json!({ "weather": refresh.weather, "overridden": refresh.overridden });
+
+    match refresh.outcome {
+        Outcome::Set(wallpaper, history_error) => {
+            logger.log(Level::Info, "wallpaper_set", json!({ "path": wallpaper.as_ref(), "weather": refresh.weather, "overridden": refresh.overridden }));
+
+            if let Some(err) = history_error {
+                logger.log(Level::Warn, "history_write_failed", json!({ "error": err.to_string() }));
+            }
+        },
+        Outcome::SetFailed(wallpaper, err) => logger.log(Level::Error, "wallpaper_set_failed", json!({ "path": wallpaper.as_ref(), "error": err.to_string() })),
+        Outcome::Kept => logger.log(Level::Info, "wallpaper_kept", weather),
+        Outcome::Settling(pending, polls) => logger.log(Level::Info, "weather_settling", json!({ "weather": pending, "polls": polls })),
+        Outcome::Paused(reason) => logger.log(Level::Info, "paused", json!({ "reason": reason.to_string() })),
+        Outcome::NoWallpapers => logger.log(Level::Warn, "no_wallpapers", json!({ "schedule": refresh.rule })),
+    }
+}
+
+/* Wait until the next refresh (not counting time paused), logging library changes and handling commands,
+ * returning early on a signal or if a command asks to refresh */
+fn wait(session: &mut Session, commands: &Receiver<Request>, signals: &Signals, logger: &mut Logger) {
+    let mut waited = 0;
+    let mut pause_reason = session.pause_reason();
+
+    while waited < session.config().interval_millis() && !signals.pending() {
+        thread::sleep(Duration::from_millis(POLL_MILLIS));
+
+        let reason = session.pause_reason();
+
+        if reason != pause_reason {
+            match &reason {
+                Some(reason) => logger.log(Level::Info, "paused", json!({ "reason": reason.to_string() })),
+                None => logger.log(Level::Info, "resumed", json!({ "remaining_millis": session.config().interval_millis().saturating_sub(waited) })),
+            }
+
+            pause_reason = reason;
+        }
+
+        if pause_reason.is_none() {
+            waited += POLL_MILLIS;
+        }
+
+        match session.update_library() {
+            Ok(Some(changes)) => log_changes(&changes, logger),
+            Ok(None) => {},
+            Err(err) => logger.log(Level::Error, "library_update_failed", json!({ "error": err.to_string() })),
+        }
+
+        while let Ok(request) = commands.try_recv() {
+            logger.log(Level::Info, "command", json!({ "command": request.command() }));
+
+            let refresh = session.handle(request);
+            log_warnings(logger);
+
+            if refresh {
+                return;
+            }
+        }
+
+        log_warnings(logger);
+    }
+}
+fn log_changes(changes: &LibraryChanges, logger: &mut Logger) {
+    let paths = |wallpapers: &Vec<Wallpaper>| wallpapers.iter()
+        .map(|wallpaper| wallpaper.as_ref().to_path_buf())
+        .collect::<Vec<PathBuf>>();
+
+    logger.log(Level::Info, "library_changed", json!({
+        "added": paths(changes.added()),
+        "removed": paths(changes.removed()),
+    }));
+}
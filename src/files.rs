@@ -1,60 +1,245 @@
-use std::{fs, io};
-use std::collections::HashSet;
-use std::path::PathBuf;
+use std::{fs, io::{self, Write}};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 use dirs::picture_dir;
-use regex::Regex;
 
-use crate::{wallpaper, Wallpaper};
+use crate::{formats, settings::Config, sidecar::{self, Precedence}, sources::Source, storage::{self, Storage}, warnings, Wallpaper};
 
-const VALID_EXTS: [&'static str; 3] = ["png", "jpg", "bmp"];
 
+/* Retrieve all wallpapers in enabled sources */
+pub fn load_all_wallpapers(config: &Config) -> io::Result<HashSet<Wallpaper>> {
+    let found: Vec<(PathBuf, &Source)> = scan_sources(config);
 
-/* Retrieve all wallpapers */
-pub fn load_all_wallpapers() -> HashSet<Wallpaper> {
-    let files = fs::read_dir(wallpapers_path().unwrap())
-        .expect("Could not read wallpaper directory")
-        .filter(|file| is_valid(file)) /* Remove invalid files */
-        .map(|file| Wallpaper::new(file.unwrap())); /* Map to a wallpaper */
+    let found_paths: HashSet<&Path> = found.iter()
+        .map(|(path, _)| path.as_path())
+        .collect();
+
+    let mut store = storage::open(config)?;
+    let all_saved: HashSet<Wallpaper> = load_saved(store.as_ref())?;
+
+    /* Saved wallpapers outside enabled sources stay saved but are not loaded */
+    let (saved, unloaded): (Vec<Wallpaper>, Vec<Wallpaper>) = all_saved.iter()
+        .cloned()
+        .partition(|wallpaper| found_paths.contains(wallpaper.as_ref()));
+
+    /* Saved wallpapers whose files no longer exist may have been renamed or moved */
+    let mut missing: Vec<Wallpaper> = unloaded.into_iter()
+        .filter(|wallpaper| !wallpaper.is_valid() && wallpaper.hash().is_some())
+        .collect();
+
+    let sidecars_win = config.sidecar_precedence() == Precedence::Sidecar;
+
+    let saved: HashSet<Wallpaper> = saved.into_iter()
+        .map(|mut wallpaper| {
+            wallpaper.ensure_hash();
+
+            if sidecars_win {
+                sidecar::apply_sidecar(&mut wallpaper);
+            }
 
-    let saved = wallpaper::load_wallpapers()
-        .unwrap_or(HashSet::new())
-        .into_iter()
-        .filter(|wallpaper| wallpaper.is_valid());
+            wallpaper
+        })
+        .collect();
+
+    let saved_paths: HashSet<&Path> = saved.iter()
+        .map(|wallpaper| wallpaper.as_ref())
+        .collect();
+
+    /* Map new files to wallpapers, re-linking missing wallpapers with matching contents */
+    let files: Vec<Wallpaper> = found.iter()
+        .filter(|(path, _)| !saved_paths.contains(path.as_path()))
+        .map(|(path, source)| {
+            let hash = hash_file(path).ok();
+
+            match missing.iter().position(|wallpaper| hash.is_some() && wallpaper.hash() == hash.as_deref()) {
+                Some(index) => {
+                    let mut wallpaper = missing.swap_remove(index);
+                    wallpaper.relink(path.clone());
+
+                    if sidecars_win {
+                        sidecar::apply_sidecar(&mut wallpaper);
+                    }
+
+                    wallpaper
+                },
+                None => new_wallpaper(path.clone(), hash, source.path(), config),
+            }
+        })
+        .collect();
 
-    let wallpapers: HashSet<Wallpaper> = saved
+    let wallpapers: HashSet<Wallpaper> = saved.into_iter()
         .chain(files)
         .collect();
 
-    wallpaper::save_wallpapers(&wallpapers).unwrap();
+    save_into(store.as_mut(), &wallpapers, all_saved, config)?;
 
-    wallpapers
+    Ok(wallpapers)
 }
 
-/* Check the file is valid */
-fn is_valid(file: &io::Result<fs::DirEntry>) -> bool {
-    match file {
-        Ok(file) => check_extension(file.path()),
-        Err(_) => false,
+/* Group wallpapers with identical file contents */
+pub fn find_duplicates(wallpapers: &HashSet<Wallpaper>) -> Vec<Vec<&Wallpaper>> {
+    let mut by_hash: HashMap<&str, Vec<&Wallpaper>> = HashMap::new();
+
+    for wallpaper in wallpapers {
+        if let Some(hash) = wallpaper.hash() {
+            by_hash.entry(hash).or_default().push(wallpaper);
+        }
+    }
+
+    let mut duplicates: Vec<Vec<&Wallpaper>> = by_hash.into_values()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort();
+            group
+        })
+        .collect();
+
+    duplicates.sort();
+    duplicates
+}
+
+/* Save loaded wallpapers, keeping saved wallpapers from disabled, excluded or unconfigured sources (and updating sidecars if they take precedence) */
+pub fn save_all_wallpapers(wallpapers: &HashSet<Wallpaper>, config: &Config) -> io::Result<()> {
+    let mut store = storage::open(config)?;
+    let saved = load_saved(store.as_ref())?;
+
+    save_into(store.as_mut(), wallpapers, saved, config)
+}
+
+/* Load wallpapers saved in store, which are none if nothing has been saved yet or the saved file was unreadable (and moved aside) */
+fn load_saved(store: &dyn Storage) -> io::Result<HashSet<Wallpaper>> {
+    match store.load_wallpapers() {
+        Ok(saved) => Ok(saved),
+        Err(err) if matches!(err.kind(), io::ErrorKind::NotFound | io::ErrorKind::InvalidData) => Ok(HashSet::new()),
+        Err(err) => Err(err),
+    }
+}
+
+/* Save loaded wallpapers into store, along with the inactive wallpapers among those it had saved */
+fn save_into(store: &mut dyn Storage, wallpapers: &HashSet<Wallpaper>, saved: HashSet<Wallpaper>, config: &Config) -> io::Result<()> {
+    /* A sidecar that cannot be written (e.g. read-only source) should not stop the wallpapers being saved */
+    if config.sidecar_precedence() == Precedence::Sidecar {
+        for (path, err) in sidecar::update_sidecars(wallpapers) {
+            warnings::warn("sidecar_update_failed", format!("Could not update sidecar for {} ({err})", path.display()));
+        }
+    }
+
+    let inactive = saved.into_iter()
+        .filter(|wallpaper| wallpaper.is_valid() || !wallpaper.is_resolved());
+
+    let merged: HashSet<Wallpaper> = wallpapers.iter()
+        .cloned()
+        .chain(inactive)
+        .collect();
+
+    store.save_wallpapers(&merged)
+}
+
+/* Create wallpaper for newly found file, passing its subfolders as keywords if enabled (its sidecar overrides both) */
+fn new_wallpaper(path: PathBuf, hash: Option<String>, root: &Path, config: &Config) -> Wallpaper {
+    let folders: Vec<String> = if config.folder_tags() {
+        path.parent()
+            .and_then(|parent| parent.strip_prefix(root).ok())
+            .map(|folders| folders.iter()
+                .filter_map(|folder| folder.to_str())
+                .map(String::from)
+                .collect())
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    let mut wallpaper = Wallpaper::new(path, hash, &folders, config);
+    sidecar::apply_sidecar(&mut wallpaper);
+
+    wallpaper
+}
+
+/* Find all valid files in enabled sources, along with the source each was found in */
+fn scan_sources(config: &Config) -> Vec<(PathBuf, &Source)> {
+    let mut found = Vec::new();
+    let mut seen = HashSet::new();
+
+    /* Never scan the data directory */
+    let data_dir = data_path().and_then(fs::canonicalize).ok();
+
+    /* Each source is scanned separately, so a file is matched against the globs of every source containing it,
+     * and belongs to the first source that accepts it */
+    for source in config.sources().iter().filter(|source| source.is_enabled()) {
+        let mut visited: HashSet<PathBuf> = data_dir.iter().cloned().collect();
+        let mut files = Vec::new();
+        scan_dir(source.path(), config.scan_depth(), &mut visited, &mut files);
+
+        found.extend(files.into_iter()
+            .filter(|path| source.matches(path))
+            .filter(|path| seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())))
+            .map(|path| (path, source)));
     }
+
+    found
 }
 
-/* Check the file's extension is valid */
-fn check_extension(file_path: PathBuf) -> bool {
-    let valid_exts: Regex = Regex::new(&format!("({})", VALID_EXTS.join("|"))).unwrap();
-    
-    let file_ext = file_path.extension()
-        .map_or("", |ext| ext.to_str().unwrap());
-        
-    valid_exts.is_match(&file_ext)
+/* Recursively find all valid files in directory, up to the given depth */
+fn scan_dir(dir: &Path, depth: usize, visited: &mut HashSet<PathBuf>, files: &mut Vec<PathBuf>) {
+    /* Skip directories already visited (e.g. through symlink loops) */
+    if !fs::canonicalize(dir).is_ok_and(|canonical| visited.insert(canonical)) {
+        return;
+    }
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.is_dir() {
+            if depth > 0 {
+                scan_dir(&path, depth - 1, visited, files);
+            }
+        } else if is_valid(&path) {
+            files.push(path);
+        }
+    }
+}
+
+/* Hash contents of file */
+pub fn hash_file(file_path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(fs::File::open(file_path)?)?;
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/* Write file without risking a truncated file on crash: write a temporary file, sync it, then rename it into place */
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(contents.as_ref())?;
+    file.sync_all()?;
+
+    fs::rename(&temp, path)?;
+
+    /* Sync directory so the rename itself is persisted (not supported on all platforms) */
+    if let Some(dir) = path.parent() {
+        let _ = fs::File::open(dir).and_then(|dir| dir.sync_all());
+    }
+
+    Ok(())
+}
+
+/* Check the file is valid */
+fn is_valid(file_path: &Path) -> bool {
+    file_path.is_file() && formats::is_supported(file_path)
 }
 
 /* Get wallpaper directory (nested in Picture directory) */
 pub fn wallpapers_path() -> io::Result<PathBuf> {
-    let wallpaper_dir: PathBuf = PathBuf::from(
-        picture_dir()
-            .ok_or(io::Error::new(io::ErrorKind::NotFound, "Picture directory not found"))?
-        ).join("weather_wallpapers");
+    let wallpaper_dir: PathBuf = picture_dir()
+        .ok_or(io::Error::new(io::ErrorKind::NotFound, "Picture directory not found"))?
+        .join("weather_wallpapers");
 
     /* Create wallpaper directory if it doesn't exist */
     if !&wallpaper_dir.exists() {
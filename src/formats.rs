@@ -0,0 +1,98 @@
+use std::{fs, io::{self, Read}, path::{Path, PathBuf}};
+
+use crate::{Error, files};
+
+const CONVERTED_DIR: &str = "converted";
+
+
+/* Supported image formats */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Gif,
+    WebP,
+    Tiff,
+}
+
+impl ImageFormat {
+    /* Match file extension, ignoring case */
+    pub fn from_extension(file_path: &Path) -> Option<ImageFormat> {
+        let ext = file_path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match ext.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" | "jpe" | "jfif" => Some(ImageFormat::Jpeg),
+            "bmp" => Some(ImageFormat::Bmp),
+            "gif" => Some(ImageFormat::Gif),
+            "webp" => Some(ImageFormat::WebP),
+            "tif" | "tiff" => Some(ImageFormat::Tiff),
+            _ => None,
+        }
+    }
+
+    /* Detect format from the magic bytes at the start of the file */
+    pub fn sniff(file_path: &Path) -> Option<ImageFormat> {
+        let mut header = [0u8; 12];
+        let len = fs::File::open(file_path).ok()?.read(&mut header).ok()?;
+        let header = &header[..len];
+
+        if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageFormat::Png)
+        } else if header.starts_with(b"\xFF\xD8\xFF") {
+            Some(ImageFormat::Jpeg)
+        } else if header.starts_with(b"BM") {
+            Some(ImageFormat::Bmp)
+        } else if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+            Some(ImageFormat::Gif)
+        } else if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+            Some(ImageFormat::WebP)
+        } else if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+            Some(ImageFormat::Tiff)
+        } else {
+            None
+        }
+    }
+
+    /* Whether desktops can reliably set the format as wallpaper without converting it */
+    fn is_native(&self) -> bool {
+        matches!(self, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Bmp)
+    }
+}
+
+
+/* Check the file has a supported extension and its contents are a supported image */
+pub fn is_supported(file_path: &Path) -> bool {
+    ImageFormat::from_extension(file_path).is_some() && ImageFormat::sniff(file_path).is_some()
+}
+
+/* Get path that can be set as wallpaper, converting to PNG (first frame for GIFs) if needed */
+pub fn settable_path(file_path: &Path, hash: Option<&str>) -> Result<PathBuf, Error> {
+    match ImageFormat::sniff(file_path) {
+        Some(format) if format.is_native() => Ok(file_path.to_path_buf()),
+        Some(_) => convert(file_path, hash).map_err(|_| Error::InvalidWallpaper),
+        None => Err(Error::InvalidWallpaper),
+    }
+}
+
+/* Convert image to PNG in the data directory, reusing previous conversions */
+fn convert(file_path: &Path, hash: Option<&str>) -> io::Result<PathBuf> {
+    let hash = match hash {
+        Some(hash) => hash.to_string(),
+        None => files::hash_file(file_path)?,
+    };
+
+    let converted_dir = files::data_path()?.join(CONVERTED_DIR);
+    fs::create_dir_all(&converted_dir)?;
+
+    let converted = converted_dir.join(format!("{hash}.png"));
+
+    if !converted.exists() {
+        image::open(file_path)
+            .and_then(|image| image.save_with_format(&converted, image::ImageFormat::Png))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    }
+
+    Ok(converted)
+}
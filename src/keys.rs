@@ -0,0 +1,112 @@
+#[cfg(unix)]
+use std::sync::{atomic::{AtomicBool, Ordering}, Once, OnceLock};
+
+/* Signals that end the process while keys are being read, so must restore the terminal first */
+#[cfg(unix)]
+const TERMINATING_SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+/* Terminal settings before keys were first read, restored by the signal handlers while a KeyReader is active.
+ * The handlers are registered once and never unregistered (which would leave the signals ignored rather than restore their default action) */
+#[cfg(unix)]
+static ORIGINAL: OnceLock<libc::termios> = OnceLock::new();
+#[cfg(unix)]
+static READING: AtomicBool = AtomicBool::new(false);
+#[cfg(unix)]
+static HANDLERS: Once = Once::new();
+
+
+/* Reads single keypresses from the terminal without waiting for enter or echoing them, restoring the terminal when dropped
+ * (or if interrupted). Output is left untouched (unlike raw mode), so progress bars and printed lines still render normally */
+pub struct KeyReader {
+    #[cfg(unix)]
+    original: Option<libc::termios>, /* Terminal settings to restore, if stdin is a terminal */
+}
+
+impl KeyReader {
+    #[cfg(unix)]
+    pub fn new() -> KeyReader {
+        /* SAFETY: termios is plain data filled in by tcgetattr, and stdin stays open for the life of the process */
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+
+            if libc::isatty(libc::STDIN_FILENO) != 1 || libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return KeyReader { original: None };
+            }
+
+            let original = *ORIGINAL.get_or_init(|| original);
+            restore_on_signal();
+
+            /* Deliver each key as it is pressed, and return from reads straight away if there is none */
+            let mut keys = original;
+            keys.c_lflag &= !(libc::ICANON | libc::ECHO);
+            keys.c_cc[libc::VMIN] = 0;
+            keys.c_cc[libc::VTIME] = 0;
+
+            READING.store(true, Ordering::SeqCst);
+
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &keys) != 0 {
+                READING.store(false, Ordering::SeqCst);
+                return KeyReader { original: None };
+            }
+
+            KeyReader { original: Some(original) }
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> KeyReader {
+        KeyReader {}
+    }
+
+    /* Get the next key pressed (lowercased), without waiting */
+    #[cfg(unix)]
+    pub fn read(&self) -> Option<char> {
+        self.original?;
+
+        let mut byte: u8 = 0;
+
+        /* SAFETY: reads at most one byte into a valid buffer */
+        let read = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+
+        (read == 1).then(|| char::from(byte).to_ascii_lowercase())
+    }
+
+    #[cfg(not(unix))]
+    pub fn read(&self) -> Option<char> {
+        None
+    }
+}
+
+#[cfg(unix)]
+impl Drop for KeyReader {
+    fn drop(&mut self) {
+        if let Some(original) = &self.original {
+            READING.store(false, Ordering::SeqCst);
+
+            /* SAFETY: restores settings previously read by tcgetattr */
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+            }
+        }
+    }
+}
+
+/* Restore the terminal settings (if keys are being read) before a terminating signal ends the process (Drop does not run then).
+ * Registered on first use only */
+#[cfg(unix)]
+fn restore_on_signal() {
+    HANDLERS.call_once(|| {
+        for signal in TERMINATING_SIGNALS {
+            /* SAFETY: the handler only reads atomics, calls tcsetattr and then the default action, all async-signal-safe */
+            let _ = unsafe {
+                signal_hook::low_level::register(signal, move || {
+                    if let (true, Some(original)) = (READING.load(Ordering::SeqCst), ORIGINAL.get()) {
+                        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+                    }
+
+                    let _ = signal_hook::low_level::emulate_default_handler(signal);
+                })
+            };
+        }
+    });
+}
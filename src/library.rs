@@ -0,0 +1,195 @@
+use std::{collections::HashSet, fmt::{self, Display}, io, path::PathBuf, sync::mpsc::{self, Receiver}, time::{Duration, Instant}};
+
+use colored::Colorize;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{files, settings::Config, wallpaper::Wallpaper};
+
+/* Time to wait after the last file event before rescanning (files may still be being written) */
+const DEBOUNCE_MILLIS: u64 = 1000;
+
+
+/* Wallpapers to choose from, kept up to date with changes to the source directories */
+pub struct Library<'a> {
+    wallpapers: HashSet<Wallpaper>,
+    collection: Option<&'a str>,
+    watcher: Option<LibraryWatcher>,
+    watch_errors: Vec<String>, /* Sources (or the whole library) that could not be watched for changes */
+    untagged: Vec<PathBuf>, /* Untagged wallpapers added since loading */
+}
+
+impl<'a> Library<'a> {
+    /* Load wallpapers (restricted to collection if given) and start watching for changes */
+    pub fn load(config: &Config, collection: Option<&'a str>) -> io::Result<Library<'a>> {
+        let (watcher, watch_errors) = match LibraryWatcher::new(config) {
+            Ok((watcher, errors)) => (Some(watcher), errors),
+            Err(err) => (None, vec![format!("Could not watch for file changes: {err}")]),
+        };
+
+        Ok(Library {
+            wallpapers: load_collection(config, collection)?,
+            collection,
+            watcher,
+            watch_errors,
+            untagged: Vec::new(),
+        })
+    }
+
+    pub fn wallpapers(&self) -> &HashSet<Wallpaper> {
+        &self.wallpapers
+    }
+
+    pub fn untagged(&self) -> &Vec<PathBuf> {
+        &self.untagged
+    }
+
+    pub fn watch_errors(&self) -> &Vec<String> {
+        &self.watch_errors
+    }
+
+    /* Replace a wallpaper with an edited copy (e.g. after rating it), ignoring wallpapers not in the library */
+    pub fn replace(&mut self, wallpaper: Wallpaper) {
+        if self.wallpapers.contains(&wallpaper) {
+            self.wallpapers.replace(wallpaper);
+        }
+    }
+
+    /* Rescan if files have changed, returning the wallpapers added and removed (the wallpapers are kept if rescanning fails) */
+    pub fn update(&mut self, config: &Config) -> io::Result<Option<LibraryChanges>> {
+        if !self.watcher.as_mut().is_some_and(LibraryWatcher::changed) {
+            return Ok(None);
+        }
+
+        let wallpapers = load_collection(config, self.collection)?;
+
+        let changes = LibraryChanges {
+            added: wallpapers.difference(&self.wallpapers).cloned().collect(),
+            removed: self.wallpapers.difference(&wallpapers).cloned().collect(),
+        };
+
+        self.untagged.retain(|path| wallpapers.iter().any(|wallpaper| wallpaper.as_ref() == path));
+        self.untagged.extend(changes.added.iter()
+            .filter(|wallpaper| wallpaper.weather.tags().is_empty())
+            .map(|wallpaper| wallpaper.as_ref().to_path_buf()));
+
+        self.wallpapers = wallpapers;
+
+        Ok((!changes.is_empty()).then_some(changes))
+    }
+}
+
+/* Load all wallpapers, restricted to collection if given */
+pub fn load_collection(config: &Config, collection: Option<&str>) -> io::Result<HashSet<Wallpaper>> {
+    let mut wallpapers = files::load_all_wallpapers(config)?;
+
+    if let Some(collection) = collection {
+        wallpapers.retain(|wallpaper| wallpaper.in_collection(collection));
+    }
+
+    Ok(wallpapers)
+}
+
+
+/* Wallpapers added and removed by a rescan */
+pub struct LibraryChanges {
+    added: Vec<Wallpaper>,
+    removed: Vec<Wallpaper>,
+}
+
+impl LibraryChanges {
+    pub fn added(&self) -> &Vec<Wallpaper> {
+        &self.added
+    }
+
+    pub fn removed(&self) -> &Vec<Wallpaper> {
+        &self.removed
+    }
+
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/* Print added and removed wallpapers */
+impl Display for LibraryChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = |wallpapers: &Vec<Wallpaper>| wallpapers.iter()
+            .map(|wallpaper| wallpaper.as_ref().display().to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let mut lines = Vec::new();
+
+        if !self.added.is_empty() {
+            lines.push(format!("{} {}", "Added:".green(), names(&self.added)));
+        }
+
+        if !self.removed.is_empty() {
+            lines.push(format!("{} {}", "Removed:".red(), names(&self.removed)));
+        }
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+
+/* Watches enabled source directories for file changes */
+struct LibraryWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    data_dir: Option<PathBuf>,
+    last_event: Option<Instant>,
+}
+
+impl LibraryWatcher {
+    /* Watch each enabled source, along with an error for each source that could not be watched */
+    fn new(config: &Config) -> notify::Result<(LibraryWatcher, Vec<String>)> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+
+        /* An unwatchable source (e.g. an unmounted drive) should not stop the others being watched */
+        let errors: Vec<String> = config.sources().iter()
+            .filter(|source| source.is_enabled())
+            .filter_map(|source| watcher.watch(source.path(), RecursiveMode::Recursive).err()
+                .map(|err| format!("Not watching {} for changes: {err}", source.path().display())))
+            .collect();
+
+        let library_watcher = LibraryWatcher {
+            _watcher: watcher,
+            events,
+            data_dir: files::data_path().ok(),
+            last_event: None,
+        };
+
+        Ok((library_watcher, errors))
+    }
+
+    /* Check whether files have changed and settled since the last check */
+    fn changed(&mut self) -> bool {
+        let relevant = self.events.try_iter()
+            .filter_map(Result::ok)
+            .filter(|event| self.is_relevant(event))
+            .count();
+
+        if relevant > 0 {
+            self.last_event = Some(Instant::now());
+        }
+
+        match self.last_event {
+            Some(last) if last.elapsed() >= Duration::from_millis(DEBOUNCE_MILLIS) => {
+                self.last_event = None;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /* Ignore access events and changes to the data directory (saved tags, converted images) */
+    fn is_relevant(&self, event: &Event) -> bool {
+        let in_data_dir = |path: &PathBuf| self.data_dir.as_ref()
+            .is_some_and(|data_dir| path.starts_with(data_dir));
+
+        matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_))
+            && !event.paths.iter().all(in_data_dir)
+    }
+}
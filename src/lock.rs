@@ -0,0 +1,51 @@
+use std::{fs::{File, OpenOptions, TryLockError}, io::{self, Read, Seek, Write}};
+
+use crate::files;
+
+const PID_FILE: &str = "instance.pid";
+
+
+/* Lock held on the PID file while wallpapers are being set (interactively or by the daemon), so only one instance sets them.
+ * The lock is released by the OS when the file is closed or the process dies, so a stale file never blocks a restart */
+pub struct PidLock {
+    file: File,
+}
+
+impl PidLock {
+    /* Lock the PID file and write our PID to it, failing with WouldBlock if another instance holds it */
+    pub fn acquire() -> io::Result<PidLock> {
+        let path = files::data_path()?.join(PID_FILE);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)?;
+
+        match file.try_lock() {
+            Ok(()) => {},
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                file.read_to_string(&mut pid)?;
+
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, format!("Already running (PID {})", pid.trim())));
+            },
+            Err(TryLockError::Error(err)) => return Err(err),
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+
+        Ok(PidLock { file })
+    }
+}
+
+/* Clear our PID but keep the file: removing it while locked would let another instance lock a new file at the same path */
+impl Drop for PidLock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
@@ -1,12 +1,15 @@
 use std::{
-    thread, time::Duration,
+    io, thread, time::Duration,
+    process::ExitCode,
     collections::HashSet, 
-    fmt::Display, 
+    sync::mpsc::Receiver,
+    fmt::{self, Display}, 
 };
 
+use clap::Parser;
 use console::Term;
-use indicatif::{ProgressBar, ProgressStyle};
-use dialoguer::Select;
+use indicatif::{HumanDuration, ProgressBar, ProgressState, ProgressStyle};
+use dialoguer::{Input, Select};
 use colored::Colorize;
 
 use rand::{distributions::{WeightedError, WeightedIndex}, prelude::*};
@@ -16,12 +19,43 @@ use strum_macros::Display;
 mod weather;
 mod weather_api;
 
+mod analysis;
+
+mod cli;
+mod control;
+mod daemon;
+mod keys;
+mod lock;
+mod pause;
+mod session;
+mod warnings;
+
+mod backups;
+mod bundle;
+mod collections;
 mod files;
+mod formats;
+mod library;
+mod metadata;
+mod rating;
+mod refresh;
+mod thumbnails;
 mod wallpaper;
 mod wallpaper_tags;
 
+mod schedule;
+mod schema;
 mod settings;
-
+mod sidecar;
+mod sources;
+mod storage;
+
+use control::{Command, Request, Response};
+use keys::KeyReader;
+use lock::PidLock;
+use pause::PauseReason;
+use refresh::RefreshMode;
+use session::{Outcome, Refresh, Session};
 use settings::Config;
 use wallpaper::Wallpaper;
 use weather::Weather;
@@ -43,15 +77,30 @@ impl From<reqwest::Error> for Error {
 }
 
 
-fn main() {
-    let mut config = settings::load_settings().unwrap_or_default();
+fn main() -> ExitCode {
+    let cli = cli::Cli::parse();
+    let loaded = settings::load_settings();
+
+    if let Some(command) = cli.command {
+        return cli::run(command, loaded).into();
+    }
+
+    let mut config = loaded.unwrap_or_default();
 
-    if files::load_all_wallpapers().is_empty() {
+    let wallpapers = match files::load_all_wallpapers(&config) {
+        Ok(wallpapers) => wallpapers,
+        Err(err) => {
+            eprintln!("{} {}", "Could not load wallpapers:".red(), err);
+            return cli::Status::Failure.into();
+        },
+    };
+
+    if wallpapers.is_empty() {
         println!("Weather Wallpaper:");
         println!("No wallpapers found. Add wallpapers to {}", 
             files::wallpapers_path().unwrap().display().to_string().bold());
         Term::stdout().read_line().unwrap();
-        return;
+        return cli::Status::NoWallpapers.into();
     }
 
     loop {
@@ -60,6 +109,7 @@ fn main() {
             .items(&format_items(vec![
                 "Start", 
                 "Tags", 
+                "Duplicates",
                 "Settings", 
                 "Quit"
             ]))
@@ -69,16 +119,25 @@ fn main() {
             .unwrap();
 
         match choice {
-            0 => start(&config),
+            0 => if let Some(collection) = collections::select_collection(&config, "Select collection", "All wallpapers") {
+                if let Err(err) = start(&config, collection.as_deref()) {
+                    println!("{} {}", "Could not start:".red(), err);
+                    Term::stdout().read_line().unwrap();
+                    Term::stdout().clear_last_lines(2).unwrap();
+                }
+            },
             1 => {
-                wallpaper_tags::edit_wallpaper_tags();
+                wallpaper_tags::tags_menu(&config);
                 Term::stdout().clear_screen().unwrap()
             },
-            2 => settings::edit_settings(&mut config).unwrap(),
-            3 => break, /* Quit */
+            2 => wallpaper_tags::show_duplicates(&config),
+            3 => settings::edit_settings(&mut config).unwrap(),
+            4 => break, /* Quit */
             _ => unreachable!()
         }
     }
+
+    ExitCode::SUCCESS
 }
 
 /* Format select options */
@@ -88,84 +147,289 @@ fn format_items<T>(options: Vec<T>) -> Vec<String> where T: Display {
         .collect()
 }
 
-/* Start wallpaper setting */
-fn start(config: &Config) {
-    let wallpapers: HashSet<Wallpaper> = files::load_all_wallpapers();
+/* Start wallpaper setting (restricted to collection if given), failing if another instance is already running */
+fn start(config: &Config, collection: Option<&str>) -> io::Result<()> {
+    let lock = PidLock::acquire()?;
+    let mut session = Session::new(config.clone(), collection)?;
+
+    if let Some(collection) = collection {
+        if session.wallpapers().is_empty() {
+            println!("No wallpapers in collection {}", collection.bold());
+            Term::stdout().read_line().unwrap();
+            Term::stdout().clear_last_lines(2).unwrap();
+            return Ok(());
+        }
+    }
+
+    /* Accept commands from other processes (e.g. window manager keybindings) */
+    let (sender, receiver) = control::channel();
+    let socket = control::serve(sender, &lock);
     
     loop {
         Term::stdout().clear_screen().unwrap();
         println!("{}", "Weather Wallpaper:".bold());
 
-        let fetch_weather: Result<Weather, Error> = weather::get_current_weather();
+        if let Some(collection) = collection {
+            println!("Collection: {}", collection);
+        }
 
-        let chosen: &Wallpaper = match fetch_weather {
-            Ok(curr_weather) => {
-                println!("Current Weather: {}", curr_weather);
-                choose_wallpaper(curr_weather, &wallpapers)
-            },
-            Err(Error::WeatherFetchFail) => {
-                println!("No weather found; choosing random wallpaper");
-                rand_choice(&wallpapers.iter().collect::<HashSet<&Wallpaper>>())
-            },
-            _ => unreachable!("API fetch returned unrecoverable error")
+        if let Err(err) = &socket {
+            println!("{} {}", "Control socket unavailable:".yellow(), err);
+        }
+
+        for err in session.watch_errors() {
+            println!("{}", err.yellow());
+        }
+
+        if !session.untagged().is_empty() {
+            println!("{} {}", "New untagged wallpapers:".yellow(), session.untagged().iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<String>>()
+                .join(", "));
+        }
+
+        let refresh: Refresh = session.refresh();
+
+        /* Wallpapers restricted by active schedule rule */
+        if let Some(rule) = &refresh.rule {
+            println!("Schedule: {}", rule);
+        }
+
+        match &refresh.weather {
+            Some(curr_weather) if refresh.overridden => println!("Override Weather: {}", curr_weather),
+            Some(curr_weather) => println!("Current Weather: {}", curr_weather),
+            None => println!("No weather found"),
         };
-    
-        print!("Chosen: ");
-        chosen.print();
-        chosen.set().unwrap();
 
-        render_progress_bar(config);
+        match &refresh.outcome {
+            Outcome::NoWallpapers => println!("No wallpapers found"),
+            Outcome::Set(_, history_error) => {
+                if refresh.weather.is_none() {
+                    println!("Choosing random wallpaper");
+                }
+
+                if let Some(err) = history_error {
+                    println!("{} {}", "Could not record selection:".yellow(), err);
+                }
+            },
+            Outcome::SetFailed(wallpaper, err) => println!("{} {} ({})", "Could not set".red(), wallpaper.as_ref().display(), err),
+            Outcome::Settling(pending, polls) => println!("Weather changed to {}; waiting for it to settle ({}/{} polls)", 
+                pending, polls, session.config().stable_polls()),
+            Outcome::Kept => println!("Weather unchanged; keeping current wallpaper"),
+            Outcome::Paused(reason) => println!("{}; keeping current wallpaper", reason),
+        }
+
+        if let Some(chosen) = session.current() {
+            print!("Chosen: ");
+            chosen.print();
+        }
+
+        println!("{}", "[n] next  [p] previous  [f] favourite  [t] edit tags  [space] pause  [s] snooze  [q] menu".dimmed());
+
+        if !render_progress_bar(&mut session, &receiver) {
+            return Ok(());
+        }
 
         println!("Now refreshing...");
         thread::sleep(Duration::from_secs(1));
     }
 }
 
-/* Render a progress bar to show how long left until wallpaper refreshes */
-fn render_progress_bar(config: &Config) {
-    let bar_style = ProgressStyle::with_template("{msg}\n[{elapsed_precise}] {wide_bar} ({eta})\t\t")
-        .unwrap();
-
-    let pb = ProgressBar::new(config.interval_millis())
+/* Render a progress bar to show how long left until wallpaper refreshes, reporting changes to the library and handling commands and keypresses.
+ * The countdown stops while paused and carries on where it stopped when resumed.
+ * Returns early if a command asks to refresh, and returns false if asked to go back to the menu */
+fn render_progress_bar(session: &mut Session, commands: &Receiver<Request>) -> bool {
+    let interval = session.config().interval_millis();
+
+    let message = match session.config().refresh_mode() {
+        RefreshMode::Interval => "Time remaining until refresh:",
+        RefreshMode::WeatherChange => "Time remaining until weather check:",
+    };
+
+    /* Time counted down and remaining (rather than elapsed), so time spent paused is left out */
+    let bar_style = ProgressStyle::with_template("{msg}\n[{counted}] {wide_bar} ({remaining})\t\t")
+        .unwrap()
+        .with_key("counted", |state: &ProgressState, w: &mut dyn fmt::Write| {
+            let secs = state.pos() / 1000;
+            let _ = write!(w, "{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+        })
+        .with_key("remaining", |state: &ProgressState, w: &mut dyn fmt::Write| {
+            let remaining = state.len().unwrap_or(0).saturating_sub(state.pos());
+            let _ = write!(w, "{:#}", HumanDuration(Duration::from_millis(remaining)));
+        });
+
+    let pb = ProgressBar::new(interval)
         .with_style(bar_style)
-        .with_message("Time remaining until refresh:");
+        .with_message(message);
+
+    let mut keys = KeyReader::new();
+    let mut pause_reason: Option<PauseReason> = None;
+    let mut counted = 0;
 
     let step_size = 30;
-    for _ in 0..config.interval_millis() / step_size {
+    while counted < interval {
         thread::sleep(Duration::from_millis(step_size));
-        pb.inc(step_size);
+
+        let reason = session.pause_reason();
+
+        if reason != pause_reason {
+            pb.set_message(match &reason {
+                Some(reason) => format!("{} (countdown stopped)", reason).yellow().to_string(),
+                None => message.to_string(),
+            });
+
+            pause_reason = reason;
+        }
+
+        if pause_reason.is_none() {
+            pb.inc(step_size);
+            counted += step_size;
+        }
+
+        match session.update_library() {
+            Ok(Some(changes)) => pb.println(changes.to_string()),
+            Ok(None) => {},
+            Err(err) => pb.println(format!("{} {}", "Could not rescan wallpapers:".red(), err)),
+        }
+
+        /* Keys send the same commands as the control socket */
+        let command = match keys.read() {
+            Some('n') => Some(Command::Next),
+            Some('p') => Some(Command::Previous),
+            Some('f') => Some(Command::ToggleFavourite),
+            Some(' ') => match pause_reason {
+                Some(PauseReason::Paused | PauseReason::Snoozed(_)) => Some(Command::Resume),
+                _ => Some(Command::Pause),
+            },
+            Some('s') => {
+                drop(keys);
+                let minutes = pb.suspend(snooze_input);
+                keys = KeyReader::new();
+                Some(Command::Snooze { minutes })
+            },
+            Some('t') => {
+                /* Give the terminal back to the tag editor */
+                drop(keys);
+                pb.suspend(|| edit_current_tags(session));
+                keys = KeyReader::new();
+                None
+            },
+            Some('q') => {
+                pb.finish_and_clear();
+                return false;
+            },
+            _ => None,
+        };
+
+        if let Some(command) = command {
+            let (request, response) = Request::new(command);
+
+            let refresh = session.handle(request);
+
+            if let Some(error) = response.try_recv().ok().as_ref().and_then(Response::error_message) {
+                pb.println(error.yellow().to_string());
+            }
+
+            if refresh {
+                pb.finish_and_clear();
+                return true;
+            }
+        }
+
+        /* Refresh early if a command asks for it */
+        while let Ok(request) = commands.try_recv() {
+            pb.println(format!("Received command: {}", request.command()));
+
+            if session.handle(request) {
+                pb.finish_and_clear();
+                return true;
+            }
+        }
     }
 
     pb.finish_and_clear();
+    true
+}
+
+/* Handle input for how long to snooze for */
+fn snooze_input() -> u64 {
+    let minutes = Input::<u64>::new()
+        .with_prompt("Snooze for how many minutes (0 to end snooze)")
+        .default(pause::SNOOZE_MINUTES)
+        .validate_with(|minutes: &u64| pause::snooze_until(*minutes).map(|_| ()))
+        .report(false)
+        .interact_text()
+        .unwrap();
+
+    Term::stdout().clear_last_lines(1).unwrap();
+
+    minutes
+}
+
+/* Edit the tags of the wallpaper currently set, then show it again */
+fn edit_current_tags(session: &mut Session) {
+    let Some(current) = session.current().cloned() else {
+        println!("No wallpaper set");
+        return;
+    };
+
+    if let Some(edited) = wallpaper_tags::edit_wallpaper(&current, session.config()) {
+        session.replace(edited);
+    }
+
+    Term::stdout().clear_screen().unwrap();
+    println!("{}", "Weather Wallpaper:".bold());
+
+    if let Some(chosen) = session.current() {
+        print!("Chosen: ");
+        chosen.print();
+    }
+}
+
+/* Choose wallpaper for the weather, or at random if there is none (wallpapers must not be empty or disabled) */
+fn pick_wallpaper<'a>(weather: Option<&Weather>, wallpapers: &HashSet<&'a Wallpaper>, config: &Config) -> &'a Wallpaper {
+    match weather {
+        Some(weather) => choose_wallpaper(weather.clone(), wallpapers, config),
+        None => rand_choice(wallpapers),
+    }
 }
 
 /* Choose random wallpaper */
-fn choose_wallpaper(weather: Weather, wallpapers: &HashSet<Wallpaper>) -> &Wallpaper {
+fn choose_wallpaper<'a>(weather: Weather, wallpapers: &HashSet<&'a Wallpaper>, config: &Config) -> &'a Wallpaper {
     /* Filter wallpapers by matching day/night */
     let day_filtered: HashSet<&Wallpaper> = wallpapers.iter()
         .filter(|w| w.weather.is_day() == weather.is_day())
+        .copied()
         .collect();
 
     /* Choose random wallpaper */
-    match weighted_choice(&weather, &day_filtered) {
+    match weighted_choice(&weather, &day_filtered, config) {
         Ok(wallpaper) => wallpaper,
         
         /* No day-appropriate wallpapers - try again with all wallpapers */
         Err(WeightedError::NoItem) => 
-            weighted_choice(&weather, &wallpapers.iter().collect::<HashSet<&Wallpaper>>()).unwrap()
+            weighted_choice(&weather, wallpapers, config).unwrap()
         ,
 
         error => error.unwrap(), /* Too many weights provided or negative weight found */
     }
 }
 
+/* Filter out wallpapers disabled by their rating (empty if all are disabled) */
+fn enabled<'a>(wallpapers: &HashSet<&'a Wallpaper>, config: &Config) -> HashSet<&'a Wallpaper> {
+    wallpapers.iter()
+        .filter(|wallpaper| wallpaper.is_enabled(config))
+        .copied()
+        .collect()
+}
+
 /* Weight wallpapers by number of matching tags, then choose a random wallpaper */
-fn weighted_choice<'a>(weather: &Weather, wallpapers: &HashSet<&'a Wallpaper>) -> Result<&'a Wallpaper, WeightedError> {
+fn weighted_choice<'a>(weather: &Weather, wallpapers: &HashSet<&'a Wallpaper>, config: &Config) -> Result<&'a Wallpaper, WeightedError> {
     let mut rng = rand::thread_rng();
 
-    /* Weight wallpapers by matching tags and favourites */
+    /* Weight wallpapers by matching tags and rating */
     let weighted: Vec<(usize, &&Wallpaper)> = wallpapers.iter()
-        .map(|wallpaper| (wallpaper.get_weight(&weather), wallpaper)) 
+        .map(|wallpaper| (wallpaper.get_weight(weather, config), wallpaper)) 
         .collect();
 
     /* Choose random wallpaper */
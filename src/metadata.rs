@@ -0,0 +1,221 @@
+use std::{f64::consts::PI, fs, io::{BufReader, Read}, path::Path};
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use exif::{Context, Exif, In, Tag, Value};
+
+use crate::weather::WeatherTag;
+
+/* Bytes searched for an XMP packet (usually stored near the start of the file) */
+const XMP_SEARCH_BYTES: u64 = 256 * 1024;
+
+/* Windows "Keywords" EXIF tag (UTF-16LE, semicolon separated) */
+const XP_KEYWORDS: Tag = Tag(Context::Tiff, 0x9C9E);
+
+const EXIF_DATE_TIME_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+
+
+/* Keywords and day/night imported from a file's name and embedded metadata */
+pub struct Imported {
+    pub keywords: Vec<String>,
+    pub is_day: Option<bool>,
+}
+
+/* Read keywords from filename tokens, EXIF and XMP, and guess day/night from capture time and location */
+pub fn import(file_path: &Path) -> Imported {
+    let mut keywords = filename_tokens(file_path);
+    keywords.extend(xmp_keywords(file_path));
+
+    let exif = read_exif(file_path);
+
+    if let Some(exif) = &exif {
+        keywords.extend(exif_keywords(exif));
+    }
+
+    Imported {
+        keywords,
+        is_day: exif.as_ref().and_then(exif_is_day),
+    }
+}
+
+/* Split file name (without extension) into words, e.g. "foggy_forest-night" */
+fn filename_tokens(file_path: &Path) -> Vec<String> {
+    file_path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map_or(Vec::new(), split_keywords)
+}
+
+/* Split text into words, keeping adjacent words together where they form a known phrase (e.g. "partly_cloudy") */
+fn split_keywords(text: &str) -> Vec<String> {
+    let tokens: Vec<&str> = text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .collect();
+
+    let mut keywords = Vec::new();
+    let mut index = 0;
+
+    while index < tokens.len() {
+        let phrase = tokens.get(index + 1)
+            .map(|next| format!("{} {}", tokens[index], next))
+            .filter(|phrase| !WeatherTag::from_keyword(phrase).is_empty());
+
+        match phrase {
+            Some(phrase) => {
+                keywords.push(phrase);
+                index += 2;
+            },
+            None => {
+                keywords.push(String::from(tokens[index]));
+                index += 1;
+            },
+        }
+    }
+
+    keywords
+}
+
+
+fn read_exif(file_path: &Path) -> Option<Exif> {
+    let mut reader = BufReader::new(fs::File::open(file_path).ok()?);
+    exif::Reader::new().read_from_container(&mut reader).ok()
+}
+
+/* Keywords from image description and Windows keywords tags */
+fn exif_keywords(exif: &Exif) -> Vec<String> {
+    let mut keywords = Vec::new();
+
+    if let Some(Value::Ascii(lines)) = exif.get_field(Tag::ImageDescription, In::PRIMARY).map(|field| &field.value) {
+        for line in lines {
+            keywords.extend(split_keywords(&String::from_utf8_lossy(line)));
+        }
+    }
+
+    if let Some(Value::Byte(bytes)) = exif.get_field(XP_KEYWORDS, In::PRIMARY).map(|field| &field.value) {
+        let utf16: Vec<u16> = bytes.chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+
+        keywords.extend(split_keywords(&String::from_utf16_lossy(&utf16)));
+    }
+
+    keywords
+}
+
+/* Keywords from XMP subject (dc:subject) list */
+fn xmp_keywords(file_path: &Path) -> Vec<String> {
+    let mut contents = Vec::new();
+
+    let read = fs::File::open(file_path)
+        .and_then(|file| file.take(XMP_SEARCH_BYTES).read_to_end(&mut contents));
+
+    if read.is_err() {
+        return Vec::new();
+    }
+
+    let contents = String::from_utf8_lossy(&contents);
+
+    let Some(subject) = between(&contents, "<dc:subject>", "</dc:subject>") else {
+        return Vec::new();
+    };
+
+    subject.split("<rdf:li")
+        .skip(1)
+        .filter_map(|item| between(item, ">", "</rdf:li>"))
+        .flat_map(split_keywords)
+        .collect()
+}
+
+/* Get text between start and end markers */
+fn between<'a>(text: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let from = text.find(start)? + start.len();
+    let to = text[from..].find(end)? + from;
+
+    Some(&text[from..to])
+}
+
+
+/* Guess day/night from whether the sun was up where and when the image was captured */
+fn exif_is_day(exif: &Exif) -> Option<bool> {
+    let latitude = gps_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?;
+    let longitude = gps_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?;
+
+    let captured = capture_time(exif, longitude)?;
+
+    Some(solar_elevation(captured, latitude, longitude) > 0.0)
+}
+
+/* Get capture time in UTC, preferring GPS time, then the timezone offset, then estimating the offset from longitude */
+fn capture_time(exif: &Exif, longitude: f64) -> Option<DateTime<Utc>> {
+    if let Some(gps_time) = gps_time(exif) {
+        return Some(gps_time);
+    }
+
+    let local = NaiveDateTime::parse_from_str(&ascii(exif, Tag::DateTimeOriginal)?, EXIF_DATE_TIME_FORMAT).ok()?;
+
+    let offset = ascii(exif, Tag::OffsetTimeOriginal)
+        .and_then(|offset| DateTime::parse_from_str(&format!("2000-01-01 00:00:00 {offset}"), "%Y-%m-%d %H:%M:%S %:z").ok())
+        .map(|date_time| *date_time.offset())
+        .or_else(|| FixedOffset::east_opt((longitude / 15.0 * 3600.0) as i32))?;
+
+    offset.from_local_datetime(&local).single().map(|date_time| date_time.with_timezone(&Utc))
+}
+
+fn gps_time(exif: &Exif) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(&ascii(exif, Tag::GPSDateStamp)?, "%Y:%m:%d").ok()?;
+
+    let [hour, minute, second] = rationals(exif, Tag::GPSTimeStamp)?;
+    let time = NaiveTime::from_hms_opt(hour as u32, minute as u32, second as u32)?;
+
+    Some(Utc.from_utc_datetime(&date.and_time(time)))
+}
+
+/* Get signed decimal degrees from degrees, minutes and seconds */
+fn gps_coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: &str) -> Option<f64> {
+    let [degrees, minutes, seconds] = rationals(exif, tag)?;
+    let degrees = degrees + minutes / 60.0 + seconds / 3600.0;
+
+    if ascii(exif, ref_tag).is_some_and(|reference| reference.eq_ignore_ascii_case(negative_ref)) {
+        Some(-degrees)
+    } else {
+        Some(degrees)
+    }
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(lines) => lines.first().map(|line| String::from_utf8_lossy(line).trim().to_string()),
+        _ => None,
+    }
+}
+
+fn rationals(exif: &Exif, tag: Tag) -> Option<[f64; 3]> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) if values.len() >= 3 => Some([0, 1, 2].map(|i| values[i].to_f64())),
+        _ => None,
+    }
+}
+
+/* Angle of the sun above the horizon in degrees (NOAA approximation) */
+fn solar_elevation(time: DateTime<Utc>, latitude: f64, longitude: f64) -> f64 {
+    let hours = time.hour() as f64 + time.minute() as f64 / 60.0 + time.second() as f64 / 3600.0;
+
+    /* Fractional year in radians */
+    let gamma = 2.0 * PI / 365.0 * (time.ordinal0() as f64 + (hours - 12.0) / 24.0);
+
+    /* Equation of time in minutes */
+    let eq_time = 229.18 * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin()
+        - 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+
+    /* Solar declination in radians */
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos() + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos() + 0.00148 * (3.0 * gamma).sin();
+
+    let solar_minutes = hours * 60.0 + eq_time + 4.0 * longitude;
+    let hour_angle = (solar_minutes / 4.0 - 180.0).to_radians();
+
+    let latitude = latitude.to_radians();
+    let cos_zenith = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+
+    90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees()
+}
@@ -0,0 +1,90 @@
+use std::{fmt::{self, Display}, path::PathBuf};
+
+#[cfg(target_os = "linux")]
+use std::{fs, path::Path};
+
+use chrono::{DateTime, Local, TimeDelta};
+
+use crate::settings::Config;
+
+/* Snooze length offered by default */
+pub const SNOOZE_MINUTES: u64 = 30;
+
+/* Longest snooze accepted (a week) */
+pub const MAX_SNOOZE_MINUTES: u64 = 7 * 24 * 60;
+
+#[cfg(target_os = "linux")]
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+
+/* Why automatic wallpaper changes are paused */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PauseReason {
+    Paused, /* Until resumed */
+    Snoozed(DateTime<Local>), /* Until the given time */
+    OnBattery,
+    Marker(PathBuf), /* While the marker file exists */
+}
+
+impl Display for PauseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PauseReason::Paused => write!(f, "Paused"),
+            PauseReason::Snoozed(until) => write!(f, "Snoozed until {}", until.format("%H:%M")),
+            PauseReason::OnBattery => write!(f, "Paused on battery"),
+            PauseReason::Marker(path) => write!(f, "Paused while {} exists", path.display()),
+        }
+    }
+}
+
+
+/* Time a snooze of minutes from now ends (None for 0 minutes, ending any snooze) */
+pub fn snooze_until(minutes: u64) -> Result<Option<DateTime<Local>>, String> {
+    if minutes == 0 {
+        return Ok(None);
+    }
+
+    if minutes > MAX_SNOOZE_MINUTES {
+        return Err(format!("Cannot snooze for more than {MAX_SNOOZE_MINUTES} minutes"));
+    }
+
+    i64::try_from(minutes).ok()
+        .and_then(|minutes| minutes.checked_mul(60))
+        .and_then(TimeDelta::try_seconds)
+        .and_then(|snooze| Local::now().checked_add_signed(snooze))
+        .map(Some)
+        .ok_or_else(|| String::from("Snooze is too long"))
+}
+
+/* Get the first configured condition for pausing that holds right now */
+pub fn automatic_reason(config: &Config) -> Option<PauseReason> {
+    if let Some(marker) = config.pause_marker().filter(|marker| marker.exists()) {
+        return Some(PauseReason::Marker(marker.to_path_buf()));
+    }
+
+    (config.pause_on_battery() && on_battery()).then_some(PauseReason::OnBattery)
+}
+
+/* Check whether any battery is discharging */
+#[cfg(target_os = "linux")]
+fn on_battery() -> bool {
+    let Ok(entries) = fs::read_dir(POWER_SUPPLY_DIR) else {
+        return false;
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .any(|entry| read_trimmed(&entry.path().join("type")).is_some_and(|kind| kind == "Battery")
+            && read_trimmed(&entry.path().join("status")).is_some_and(|status| status == "Discharging"))
+}
+
+/* Battery state is only read on Linux */
+#[cfg(not(target_os = "linux"))]
+fn on_battery() -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|contents| contents.trim().to_string())
+}
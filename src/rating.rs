@@ -0,0 +1,54 @@
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+pub const MAX_RATING: u8 = 5;
+
+/* Rating given to unrated wallpapers */
+const DEFAULT_RATING: u8 = 3;
+
+/* Default multiplier applied to the weight of a wallpaper for each star rating */
+pub const DEFAULT_RATING_WEIGHTS: [usize; MAX_RATING as usize + 1] = [0, 1, 2, 3, 4, 6];
+
+
+/* 0-5 star rating of a wallpaper */
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Rating(u8);
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating(DEFAULT_RATING)
+    }
+}
+
+impl Rating {
+    pub fn new(stars: u8) -> Rating {
+        Rating(stars.min(MAX_RATING))
+    }
+
+    pub fn stars(&self) -> u8 {
+        self.0
+    }
+
+    /* All possible ratings, from 0 to 5 stars */
+    pub fn all() -> impl Iterator<Item = Rating> {
+        (0..=MAX_RATING).map(Rating)
+    }
+}
+
+/* Print rating as filled and empty stars */
+impl Display for Rating {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}",
+            "\u{2605}".repeat(self.0 as usize),
+            "\u{2606}".repeat((MAX_RATING - self.0) as usize)
+        )
+    }
+}
+
+/* Clamp saved star counts to the maximum rating */
+impl<'de> Deserialize<'de> for Rating {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        Ok(Rating::new(u8::deserialize(deserializer)?))
+    }
+}
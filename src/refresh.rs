@@ -0,0 +1,109 @@
+use std::{collections::HashSet, time::{Duration, Instant}};
+
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumIter};
+
+use crate::{settings::Config, wallpaper::Wallpaper, weather::Weather};
+
+
+/* When to replace the current wallpaper */
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Display, EnumIter)]
+pub enum RefreshMode {
+    /* Replace wallpaper every refresh interval */
+    #[default]
+    Interval,
+    /* Poll weather every refresh interval, only replacing wallpaper when the weather changes */
+    #[strum(serialize = "Weather change")]
+    WeatherChange,
+}
+
+
+/* Wallpaper currently set and the weather it was chosen for */
+pub struct RefreshState {
+    current: Option<Wallpaper>,
+    weather: Option<Weather>,
+    set_at: Instant,
+    pending: Option<(Weather, u32)>, /* Changed weather and number of consecutive polls it has persisted for */
+}
+
+impl RefreshState {
+    pub fn new() -> RefreshState {
+        RefreshState {
+            current: None,
+            weather: None,
+            set_at: Instant::now(),
+            pending: None,
+        }
+    }
+
+    pub fn current(&self) -> Option<&Wallpaper> {
+        self.current.as_ref()
+    }
+
+    pub fn weather(&self) -> Option<&Weather> {
+        self.weather.as_ref()
+    }
+
+    pub fn pending(&self) -> Option<&(Weather, u32)> {
+        self.pending.as_ref()
+    }
+
+    /* Check whether the wallpaper should be replaced given the latest weather and allowed wallpapers */
+    pub fn should_change(&mut self, weather: Option<&Weather>, allowed: &HashSet<&Wallpaper>, config: &Config) -> bool {
+        let Some(current) = self.current.clone() else {
+            return true;
+        };
+
+        /* Current wallpaper no longer allowed (e.g. schedule rule changed or file removed) */
+        if !allowed.contains(&current) {
+            return true;
+        }
+
+        match config.refresh_mode() {
+            RefreshMode::Interval => true,
+            RefreshMode::WeatherChange => {
+                self.weather_settled(&current, weather, config) || self.dwell_exceeded(config)
+            },
+        }
+    }
+
+    /* Check whether changed weather has persisted for long enough, or the current wallpaper suits it too poorly to keep */
+    fn weather_settled(&mut self, current: &Wallpaper, weather: Option<&Weather>, config: &Config) -> bool {
+        let Some(weather) = weather.filter(|weather| self.weather.as_ref() != Some(*weather)) else {
+            /* Weather unchanged (or unknown) - discard any flicker */
+            self.pending = None;
+            return false;
+        };
+
+        let polls = match &self.pending {
+            Some((pending, polls)) if pending == weather => polls + 1,
+            _ => 1,
+        };
+
+        self.pending = Some((weather.clone(), polls));
+
+        polls >= config.stable_polls() 
+            || current.get_weight(weather, config) < config.replace_below_weight()
+    }
+
+    /* Check whether the wallpaper has been shown for longer than the maximum dwell time */
+    fn dwell_exceeded(&self, config: &Config) -> bool {
+        config.max_dwell_millis()
+            .is_some_and(|max_dwell| self.set_at.elapsed() >= Duration::from_millis(max_dwell))
+    }
+
+    /* Replace the current wallpaper with an edited copy, if it is the same wallpaper */
+    pub fn replace_current(&mut self, wallpaper: Wallpaper) {
+        if self.current.as_ref() == Some(&wallpaper) {
+            self.current = Some(wallpaper);
+        }
+    }
+
+    /* Record newly set wallpaper */
+    pub fn set(&mut self, wallpaper: Wallpaper, weather: Option<Weather>) {
+        self.current = Some(wallpaper);
+        self.weather = weather;
+        self.set_at = Instant::now();
+        self.pending = None;
+    }
+}
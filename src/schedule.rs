@@ -0,0 +1,195 @@
+use std::{collections::HashSet, fmt::{self, Display}};
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use colored::Colorize;
+use console::Term;
+use dialoguer::{Input, MultiSelect, Select};
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::{collections, format_items, schema, settings::Config, wallpaper::Wallpaper, weather::WeatherTag};
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun
+];
+
+const TIME_FORMAT: &str = "%H:%M";
+
+
+/* Rule restricting which wallpapers can be chosen during a time window */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleRule {
+    name: String,
+    days: Vec<Weekday>, /* Days the rule applies on (every day if empty) */
+    start: NaiveTime,
+    end: NaiveTime, /* Window wraps past midnight if end is before start, and lasts a full day if it equals start */
+
+    #[serde(default, serialize_with = "schema::sorted")]
+    tags: HashSet<WeatherTag>, /* Wallpaper must have at least one of these tags (any if empty) */
+    #[serde(default, serialize_with = "schema::sorted")]
+    exclude_tags: HashSet<WeatherTag>, /* Wallpaper must have none of these tags */
+    #[serde(default)]
+    collection: Option<String>, /* Wallpaper must belong to this collection */
+}
+
+/* Print name, days and time window of rule */
+impl Display for ScheduleRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days = if self.days.is_empty() {
+            String::from("every day")
+        } else {
+            self.days.iter()
+                .map(Weekday::to_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+
+        write!(f, "{} ({} {}-{}{})",
+            self.name.bold(),
+            days,
+            self.start.format(TIME_FORMAT),
+            self.end.format(TIME_FORMAT),
+            self.collection.as_ref().map_or(String::new(), |collection| format!(", {collection}"))
+        )
+    }
+}
+
+impl ScheduleRule {
+    /* Check whether the rule applies at the given time */
+    pub fn is_active(&self, now: NaiveDateTime) -> bool {
+        let time = now.time();
+
+        /* Times after midnight belong to the previous day's window (equal times wrap round to cover the whole day) */
+        let (in_window, day) = if self.start < self.end {
+            (self.start <= time && time < self.end, now.weekday())
+        } else if time >= self.start {
+            (true, now.weekday())
+        } else {
+            (time < self.end, now.weekday().pred())
+        };
+
+        in_window && (self.days.is_empty() || self.days.contains(&day))
+    }
+
+    /* Check whether the wallpaper is allowed by the rule's filters */
+    pub fn allows(&self, wallpaper: &Wallpaper) -> bool {
+        let tags = wallpaper.weather.tags();
+
+        (self.tags.is_empty() || !self.tags.is_disjoint(tags))
+            && self.exclude_tags.is_disjoint(tags)
+            && self.collection.as_ref().is_none_or(|collection| wallpaper.in_collection(collection))
+    }
+}
+
+
+/* Get the first rule active right now */
+pub fn active_rule(config: &Config) -> Option<&ScheduleRule> {
+    let now = Local::now().naive_local();
+
+    config.schedule().iter()
+        .find(|rule| rule.is_active(now))
+}
+
+/* Restrict wallpapers to those allowed by the active rule (empty if the rule allows none) */
+pub fn allowed<'a>(rule: Option<&ScheduleRule>, wallpapers: &'a HashSet<Wallpaper>) -> HashSet<&'a Wallpaper> {
+    wallpapers.iter()
+        .filter(|wallpaper| rule.is_none_or(|rule| rule.allows(wallpaper)))
+        .collect()
+}
+
+
+/* Add or remove schedule rules */
+pub fn edit_schedule(config: &mut Config) {
+    loop {
+        let mut items: Vec<String> = config.schedule().iter()
+            .map(ScheduleRule::to_string)
+            .collect();
+
+        items.push(String::from("Add rule"));
+        items.push(String::from("Back"));
+
+        let choice = Select::new()
+            .with_prompt("Schedule rules (select a rule to remove it)")
+            .items(&format_items(items))
+            .default(config.schedule().len())
+            .report(false)
+            .interact_opt()
+            .unwrap();
+
+        let count = config.schedule().len();
+
+        match choice {
+            Some(index) if index < count => { config.schedule_mut().remove(index); },
+            Some(index) if index == count => {
+                if let Some(rule) = new_rule(config) {
+                    config.schedule_mut().push(rule);
+                }
+            },
+            _ => return, /* Back */
+        }
+    }
+}
+
+/* Handle input for a new rule */
+fn new_rule(config: &Config) -> Option<ScheduleRule> {
+    let term = Term::stdout();
+
+    let name = Input::<String>::new()
+        .with_prompt("Rule name")
+        .interact_text()
+        .unwrap();
+
+    let days = MultiSelect::new()
+        .with_prompt("Select days (none for every day)")
+        .items(&WEEKDAYS)
+        .report(false)
+        .interact_opt()
+        .unwrap()?;
+
+    let start = time_input("Start time (HH:MM)", "09:00");
+    let end = time_input("End time (HH:MM, same as start for all day)", "17:00");
+
+    let tags = tags_input("Select tags to allow (none for any)")?;
+    let exclude_tags = tags_input("Select tags to exclude")?;
+    let collection = collections::select_collection(config, "Select collection to allow", "Any collection")?;
+
+    term.clear_last_lines(3).unwrap();
+
+    Some(ScheduleRule {
+        name,
+        days: days.into_iter().map(|i| WEEKDAYS[i]).collect(),
+        start,
+        end,
+        tags,
+        exclude_tags,
+        collection,
+    })
+}
+
+fn time_input(prompt: &str, initial: &str) -> NaiveTime {
+    let input = Input::<String>::new()
+        .with_prompt(prompt)
+        .with_initial_text(initial)
+        .validate_with(|input: &String|
+            NaiveTime::parse_from_str(input, TIME_FORMAT)
+                .map(|_| ())
+                .map_err(|_| "Time must be in HH:MM format")
+        )
+        .interact_text()
+        .unwrap();
+
+    NaiveTime::parse_from_str(&input, TIME_FORMAT).unwrap()
+}
+
+fn tags_input(prompt: &str) -> Option<HashSet<WeatherTag>> {
+    let tags: Vec<WeatherTag> = WeatherTag::iter().collect();
+
+    let input = MultiSelect::new()
+        .with_prompt(prompt)
+        .items(&tags)
+        .report(false)
+        .interact_opt()
+        .unwrap()?;
+
+    Some(input.into_iter().map(|i| tags[i].clone()).collect())
+}
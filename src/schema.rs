@@ -0,0 +1,146 @@
+use std::{collections::HashSet, fs, io, path::{Path, PathBuf}};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize, Serializer, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{files, warnings};
+
+/* Upgrades saved data from one schema version to the next */
+pub type Migration = fn(Value) -> Value;
+
+
+/* Saved data tagged with the schema version it was written in */
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    version: usize,
+    data: &'a T,
+}
+
+#[derive(Deserialize)]
+struct SavedEnvelope {
+    version: usize,
+    data: Value,
+}
+
+
+/* Load versioned file, migrating it to the current version (the number of migrations).
+ * Files without a version are version 0. Unreadable files are moved aside as a backup rather than left to be overwritten */
+pub fn load<T: DeserializeOwned>(path: &Path, migrations: &[Migration]) -> io::Result<T> {
+    let contents = fs::read_to_string(path)?;
+
+    parse(&contents, migrations).map_err(|err| {
+        let error = io::Error::new(io::ErrorKind::InvalidData, err);
+
+        match back_up(path) {
+            Ok(backup) => warnings::warn("file_moved_aside", format!("Could not read {} ({error}), moved it to {}", path.display(), backup.display())),
+            Err(backup_err) => warnings::warn("file_unreadable", format!("Could not read {} ({error}) or back it up ({backup_err})", path.display())),
+        }
+
+        error
+    })
+}
+
+/* Save file tagged with the current version, returning whether its contents changed */
+pub fn save<T: Serialize>(path: &Path, data: &T, migrations: &[Migration]) -> io::Result<bool> {
+    let envelope = Envelope {
+        version: migrations.len(),
+        data,
+    };
+
+    let contents = serde_json::to_string_pretty(&envelope)?;
+
+    if fs::read_to_string(path).is_ok_and(|saved| saved == contents) {
+        return Ok(false);
+    }
+
+    files::write_atomic(path, contents)?;
+    Ok(true)
+}
+
+/* Serialize set in sorted order, so unchanged data is saved identically */
+pub fn sorted<T: Serialize + Ord, S: Serializer>(set: &HashSet<T>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut items: Vec<&T> = set.iter().collect();
+    items.sort();
+
+    items.serialize(serializer)
+}
+
+/* Parse saved contents (versioned or not), migrating them to the current version */
+pub fn parse<T: DeserializeOwned>(contents: &str, migrations: &[Migration]) -> Result<T, String> {
+    let value: Value = serde_json::from_str(contents).map_err(|err| err.to_string())?;
+
+    let is_envelope = value.get("version").is_some() && value.get("data").is_some();
+
+    let SavedEnvelope { version, data } = if is_envelope {
+        serde_json::from_value(value).map_err(|err| err.to_string())?
+    } else {
+        SavedEnvelope { version: 0, data: value }
+    };
+
+    if version > migrations.len() {
+        return Err(format!("saved by a newer version (schema {version}, expected at most {})", migrations.len()));
+    }
+
+    let data = migrations[version..].iter()
+        .fold(data, |data, migrate| migrate(data));
+
+    serde_json::from_value(data).map_err(|err| err.to_string())
+}
+
+/* Move file to a timestamped backup next to it */
+fn back_up(path: &Path) -> io::Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".unreadable-{}", Local::now().format("%Y%m%d-%H%M%S")));
+
+    let backup = PathBuf::from(backup);
+    fs::rename(path, &backup)?;
+
+    Ok(backup)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /* Each migration records that it ran */
+    const MIGRATIONS: [Migration; 2] = [
+        |data| ran(data, 0),
+        |data| ran(data, 1),
+    ];
+
+    fn ran(mut data: Value, migration: usize) -> Value {
+        data["ran"].as_array_mut().unwrap().push(json!(migration));
+        data
+    }
+
+    #[test]
+    fn unversioned_file_runs_every_migration() {
+        let data: Value = parse(r#"{"ran": []}"#, &MIGRATIONS).unwrap();
+        assert_eq!(data["ran"], json!([0, 1]));
+    }
+
+    #[test]
+    fn versioned_file_runs_later_migrations() {
+        let data: Value = parse(r#"{"version": 1, "data": {"ran": []}}"#, &MIGRATIONS).unwrap();
+        assert_eq!(data["ran"], json!([1]));
+    }
+
+    #[test]
+    fn saved_file_loads_unchanged() {
+        let envelope = Envelope { version: MIGRATIONS.len(), data: &json!({"ran": []}) };
+        let contents = serde_json::to_string(&envelope).unwrap();
+
+        let data: Value = parse(&contents, &MIGRATIONS).unwrap();
+        assert_eq!(data["ran"], json!([]));
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let result = parse::<Value>(r#"{"version": 3, "data": {"ran": []}}"#, &MIGRATIONS);
+        assert!(result.unwrap_err().contains("newer version"));
+    }
+}
@@ -0,0 +1,346 @@
+use std::{collections::{HashMap, HashSet}, io, path::PathBuf, time::{Duration, Instant}};
+
+use chrono::{DateTime, Local};
+
+use crate::{control::{Command, Request, Response, StatusReport}, enabled, files, library::{Library, LibraryChanges}, pause::{self, PauseReason}, pick_wallpaper, rating::{self, Rating}, refresh::RefreshState, schedule, settings::{self, Config}, storage::{self, Storage}, wallpaper::Wallpaper, weather::{self, DayNight, Weather, WeatherTag}, Error};
+
+/* Number of previously set wallpapers remembered for going back */
+const HISTORY_LENGTH: usize = 20;
+
+/* How often to check the configured conditions for pausing (e.g. battery state) */
+const PAUSE_CHECK_MILLIS: u64 = 1000;
+
+
+/* What a refresh did */
+pub enum Outcome {
+    Set(Wallpaper, Option<io::Error>), /* With any error recording it in the selection history */
+    SetFailed(Wallpaper, Error),
+    Kept,
+    Settling(Weather, u32), /* Weather changed, but has not yet persisted for enough polls */
+    Paused(PauseReason),
+    NoWallpapers,
+}
+
+/* Weather and schedule rule a refresh used, and what it did */
+pub struct Refresh {
+    pub weather: Option<Weather>,
+    pub overridden: bool, /* Weather was set by a command rather than fetched */
+    pub rule: Option<String>,
+    pub outcome: Outcome,
+}
+
+
+/* Refresh loop state shared by the interactive view and the daemon, controllable by commands */
+pub struct Session<'a> {
+    config: Config,
+    collection: Option<&'a str>,
+    library: Library<'a>,
+    store: Box<dyn Storage>,
+    state: RefreshState,
+    previous: Vec<Wallpaper>, /* Wallpapers set before the current one, most recent last */
+    queued: Option<Wallpaper>, /* Wallpaper to set on the next refresh instead of choosing one */
+    skip: bool, /* Replace the wallpaper on the next refresh whatever the weather */
+    paused: bool, /* Paused until resumed */
+    snoozed_until: Option<DateTime<Local>>,
+    automatic_pause: Option<PauseReason>, /* Configured condition for pausing that held when last checked */
+    pause_checked_at: Option<Instant>,
+    override_weather: Option<Weather>,
+    unfavourited: HashMap<PathBuf, Rating>, /* Ratings of wallpapers before they were favourited, restored when toggled off */
+}
+
+impl<'a> Session<'a> {
+    /* Load wallpapers (restricted to collection if given) and open storage */
+    pub fn new(config: Config, collection: Option<&'a str>) -> io::Result<Session<'a>> {
+        Ok(Session {
+            library: Library::load(&config, collection)?,
+            store: storage::open(&config)?,
+            config,
+            collection,
+            state: RefreshState::new(),
+            previous: Vec::new(),
+            queued: None,
+            skip: false,
+            paused: false,
+            snoozed_until: None,
+            automatic_pause: None,
+            pause_checked_at: None,
+            override_weather: None,
+            unfavourited: HashMap::new(),
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn wallpapers(&self) -> &HashSet<Wallpaper> {
+        self.library.wallpapers()
+    }
+
+    pub fn untagged(&self) -> &Vec<PathBuf> {
+        self.library.untagged()
+    }
+
+    /* Sources that could not be watched for changes */
+    pub fn watch_errors(&self) -> &Vec<String> {
+        self.library.watch_errors()
+    }
+
+    pub fn current(&self) -> Option<&Wallpaper> {
+        self.state.current()
+    }
+
+    /* Rescan if files have changed, returning the wallpapers added and removed */
+    pub fn update_library(&mut self) -> io::Result<Option<LibraryChanges>> {
+        self.library.update(&self.config)
+    }
+
+    /* Reload settings, wallpapers and storage */
+    pub fn reload(&mut self) -> io::Result<()> {
+        let config = settings::load_settings()?;
+
+        self.store = storage::open(&config)?;
+        self.library = Library::load(&config, self.collection)?;
+        self.config = config;
+        self.pause_checked_at = None;
+
+        Ok(())
+    }
+
+    /* Why automatic changes are paused, if they are */
+    pub fn pause_reason(&mut self) -> Option<PauseReason> {
+        if self.paused {
+            return Some(PauseReason::Paused);
+        }
+
+        if let Some(until) = self.snoozed_until {
+            if Local::now() < until {
+                return Some(PauseReason::Snoozed(until));
+            }
+
+            self.snoozed_until = None;
+        }
+
+        if self.pause_checked_at.is_none_or(|checked_at| checked_at.elapsed() >= Duration::from_millis(PAUSE_CHECK_MILLIS)) {
+            self.automatic_pause = pause::automatic_reason(&self.config);
+            self.pause_checked_at = Some(Instant::now());
+        }
+
+        self.automatic_pause.clone()
+    }
+
+    /* Get the weather and replace the wallpaper if it should change (or a command asked for it) */
+    pub fn refresh(&mut self) -> Refresh {
+        let pause_reason = self.pause_reason();
+
+        let rule = schedule::active_rule(&self.config);
+        let rule_name = rule.map(ToString::to_string);
+        let allowed: HashSet<&Wallpaper> = enabled(&schedule::allowed(rule, self.library.wallpapers()), &self.config);
+
+        let overridden = self.override_weather.is_some();
+        let curr_weather: Option<Weather> = match &self.override_weather {
+            Some(override_weather) => Some(override_weather.clone()),
+            None => weather::get_current_weather().ok(),
+        };
+
+        let refresh = |outcome| Refresh {
+            weather: curr_weather.clone(),
+            overridden,
+            rule: rule_name.clone(),
+            outcome,
+        };
+
+        if allowed.is_empty() {
+            return refresh(Outcome::NoWallpapers);
+        }
+
+        if let Some(queued) = self.queued.take() {
+            return refresh(self.set(queued, curr_weather.clone(), false));
+        }
+
+        let skip = std::mem::take(&mut self.skip);
+
+        if skip || (pause_reason.is_none() && self.state.should_change(curr_weather.as_ref(), &allowed, &self.config)) {
+            /* Avoid choosing the current wallpaper again when skipping */
+            let candidates: HashSet<&Wallpaper> = match self.state.current() {
+                Some(current) if skip && allowed.len() > 1 => allowed.iter()
+                    .filter(|wallpaper| **wallpaper != current)
+                    .copied()
+                    .collect(),
+                _ => allowed,
+            };
+
+            let chosen = pick_wallpaper(curr_weather.as_ref(), &candidates, &self.config).clone();
+            return refresh(self.set(chosen, curr_weather.clone(), true));
+        }
+
+        refresh(match (pause_reason, self.state.pending()) {
+            (Some(pause_reason), _) => Outcome::Paused(pause_reason),
+            (None, Some((pending, polls))) => Outcome::Settling(pending.clone(), *polls),
+            (None, None) => Outcome::Kept,
+        })
+    }
+
+    /* Handle command, returning whether to refresh now */
+    pub fn handle(&mut self, request: Request) -> bool {
+        let mut refresh = false;
+
+        let response = match request.command().clone() {
+            Command::Next => {
+                self.skip = true;
+                refresh = true;
+                Response::ok()
+            },
+            Command::Previous => match self.previous.pop() {
+                Some(previous) => {
+                    self.queued = Some(previous);
+                    refresh = true;
+                    Response::ok()
+                },
+                None => Response::error("No previous wallpaper"),
+            },
+            Command::Pause => {
+                self.paused = true;
+                Response::ok()
+            },
+            Command::Resume => {
+                self.paused = false;
+                self.snoozed_until = None;
+                Response::ok()
+            },
+            Command::Snooze { minutes } => match pause::snooze_until(minutes) {
+                Ok(until) => {
+                    self.snoozed_until = until;
+                    Response::ok()
+                },
+                Err(err) => Response::error(err),
+            },
+            Command::FavouriteCurrent => match self.favourite_current(false) {
+                Ok(()) => Response::ok(),
+                Err(err) => Response::error(err),
+            },
+            Command::ToggleFavourite => match self.favourite_current(true) {
+                Ok(()) => Response::ok(),
+                Err(err) => Response::error(err),
+            },
+            Command::SetOverrideWeather { tags, time } => match override_weather(&tags, time) {
+                Ok(override_weather) => {
+                    /* Replace the wallpaper straight away to suit the new weather */
+                    self.skip = override_weather.is_some();
+                    self.override_weather = override_weather;
+                    refresh = true;
+                    Response::ok()
+                },
+                Err(err) => Response::error(err),
+            },
+            Command::Status => Response::status(self.status()),
+            Command::ReloadConfig => match self.reload() {
+                Ok(()) => Response::ok(),
+                Err(err) => Response::error(format!("Could not reload settings: {err}")),
+            },
+        };
+
+        request.respond(response);
+        refresh
+    }
+
+    fn status(&mut self) -> StatusReport {
+        let pause_reason = self.pause_reason();
+
+        StatusReport {
+            pid: std::process::id(),
+            collection: self.collection.map(String::from),
+            current: self.state.current().map(|wallpaper| wallpaper.as_ref().to_path_buf()),
+            weather: self.state.weather().cloned(),
+            override_weather: self.override_weather.clone(),
+            paused: pause_reason.is_some(),
+            pause_reason: pause_reason.map(|reason| reason.to_string()),
+        }
+    }
+
+    /* Set wallpaper and record it, remembering the replaced wallpaper for going back if asked */
+    fn set(&mut self, wallpaper: Wallpaper, weather: Option<Weather>, remember: bool) -> Outcome {
+        if let Err(err) = wallpaper.set() {
+            return Outcome::SetFailed(wallpaper, err);
+        }
+
+        let history_error = self.store.record_selection(&wallpaper, weather.as_ref()).err();
+
+        if let Some(current) = self.state.current().filter(|_| remember) {
+            self.previous.push(current.clone());
+
+            if self.previous.len() > HISTORY_LENGTH {
+                self.previous.remove(0);
+            }
+        }
+
+        self.state.set(wallpaper.clone(), weather);
+        Outcome::Set(wallpaper, history_error)
+    }
+
+    /* Replace a wallpaper with an edited copy (e.g. after editing its tags) */
+    pub fn replace(&mut self, wallpaper: Wallpaper) {
+        self.state.replace_current(wallpaper.clone());
+        self.library.replace(wallpaper);
+    }
+
+    /* Give the current wallpaper the highest rating (or if toggling and it has it already, the rating it had before) and save it */
+    fn favourite_current(&mut self, toggle: bool) -> Result<(), String> {
+        let current = self.state.current()
+            .ok_or("No wallpaper set")?;
+
+        let mut wallpapers = files::load_all_wallpapers(&self.config)
+            .map_err(|err| format!("Could not load wallpapers: {err}"))?;
+
+        let mut favourite = wallpapers.get(current)
+            .cloned()
+            .ok_or("Current wallpaper no longer exists")?;
+
+        let path = favourite.as_ref().to_path_buf();
+        let favourited = favourite.rating().stars() == rating::MAX_RATING;
+
+        favourite.set_rating(if toggle && favourited {
+            self.unfavourited.remove(&path).unwrap_or_default()
+        } else {
+            if !favourited {
+                self.unfavourited.insert(path, favourite.rating());
+            }
+
+            Rating::new(rating::MAX_RATING)
+        });
+
+        wallpapers.replace(favourite.clone());
+
+        files::save_all_wallpapers(&wallpapers, &self.config)
+            .map_err(|err| format!("Could not save rating: {err}"))?;
+
+        self.replace(favourite);
+        Ok(())
+    }
+}
+
+
+/* Weather given by a command, or None to clear the override */
+fn override_weather(keywords: &[String], time: Option<DayNight>) -> Result<Option<Weather>, String> {
+    if keywords.is_empty() && time.is_none() {
+        return Ok(None);
+    }
+
+    let mut tags: HashSet<WeatherTag> = HashSet::new();
+
+    for keyword in keywords {
+        let matched = WeatherTag::from_keyword(keyword);
+
+        if matched.is_empty() {
+            return Err(format!("Unknown weather tag: {keyword}"));
+        }
+
+        tags.extend(matched);
+    }
+
+    let mut weather = Weather::default();
+    weather.set_tags(tags);
+    weather.set_is_day(time.and_then(|time| time.is_day()));
+
+    Ok(Some(weather))
+}
@@ -1,26 +1,75 @@
-use std::{fs, io, path::PathBuf};
+use std::{io, path::{Path, PathBuf}};
 
 use console::Term;
 use dialoguer::{Input, Select};
 use serde::{Deserialize, Serialize};
 
-use crate::{files, format_items};
+use strum::IntoEnumIterator;
+
+use crate::{analysis::Detection, files, format_items, rating::{self, Rating}, refresh::RefreshMode, schedule::{self, ScheduleRule}, schema::{self, Migration}, sidecar::Precedence, sources::{self, Source}, storage::{self, Backend}, thumbnails};
 
 const INTERVAL_MILLIS: u64 = 5 * 60 * 1000;
 
+const SCAN_DEPTH: usize = 8;
+
+const DETECTION_CONFIDENCE: f32 = 0.6;
+
+const STABLE_POLLS: u32 = 2;
+const REPLACE_BELOW_WEIGHT: usize = 0; /* Disabled - always wait for changed weather to persist */
+
 const SAVED_SETTINGS_FILE: &str = "settings.json";
 
+/* Migrations from each version of the saved settings file to the next */
+const MIGRATIONS: [Migration; 1] = [
+    |settings| settings, /* 0 -> 1: unversioned settings need no changes (missing fields take defaults) */
+];
+
 
 /* Settings config */
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     interval: u64, /* Refresh interval in millis */
+    refresh_mode: RefreshMode,
+    max_dwell: Option<u64>, /* Maximum time to keep a wallpaper in weather change mode in millis */
+    stable_polls: u32, /* Consecutive polls changed weather must persist for before replacing wallpaper */
+    replace_below_weight: usize, /* Replace wallpaper immediately if its weight for changed weather is below this (0 disables) */
+    rating_weights: [usize; rating::MAX_RATING as usize + 1], /* Weight multiplier for each star rating */
+    schedule: Vec<ScheduleRule>, /* Time windows restricting which wallpapers can be chosen */
+    sources: Vec<Source>, /* Directories to load wallpapers from */
+    scan_depth: usize, /* Maximum depth of subfolders to search for wallpapers */
+    folder_tags: bool, /* Tag new wallpapers by the names of their subfolders */
+    import_metadata: bool, /* Tag new wallpapers from filename keywords and EXIF/XMP metadata */
+    detection: Detection, /* Whether to analyse new wallpapers for day/night */
+    detection_confidence: f32, /* Confidence needed to apply day/night automatically */
+    suggest_tags: bool, /* Suggest weather tags for new wallpapers from their colours */
+    sidecar_precedence: Precedence, /* Whether sidecar files or the central tags file wins */
+    storage: Backend, /* Where wallpapers, collections and selection history are stored */
+    pause_on_battery: bool, /* Pause automatic changes while running on battery */
+    pause_marker: Option<PathBuf>, /* Pause automatic changes while this file exists (e.g. created during presentations) */
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self { 
             interval: INTERVAL_MILLIS, 
+            refresh_mode: RefreshMode::default(),
+            max_dwell: None,
+            stable_polls: STABLE_POLLS,
+            replace_below_weight: REPLACE_BELOW_WEIGHT,
+            rating_weights: rating::DEFAULT_RATING_WEIGHTS,
+            schedule: Vec::new(),
+            sources: vec![Source::default()],
+            scan_depth: SCAN_DEPTH,
+            folder_tags: false,
+            import_metadata: true,
+            detection: Detection::default(),
+            detection_confidence: DETECTION_CONFIDENCE,
+            suggest_tags: true,
+            sidecar_precedence: Precedence::default(),
+            storage: Backend::default(),
+            pause_on_battery: false,
+            pause_marker: None,
         }
     }
 }
@@ -33,6 +82,87 @@ impl Config {
     pub fn interval_millis(&self) -> u64 {
         self.interval
     }
+
+    pub fn refresh_mode(&self) -> RefreshMode {
+        self.refresh_mode
+    }
+
+    pub fn max_dwell_millis(&self) -> Option<u64> {
+        self.max_dwell
+    }
+
+    pub fn stable_polls(&self) -> u32 {
+        self.stable_polls
+    }
+
+    pub fn replace_below_weight(&self) -> usize {
+        self.replace_below_weight
+    }
+
+    fn max_dwell_mins(&self) -> String {
+        self.max_dwell.map_or(String::from("none"), |max_dwell| 
+            format!("{} mins", max_dwell as f32 / (60.0 * 1000.0)))
+    }
+
+    pub fn rating_weight(&self, rating: Rating) -> usize {
+        self.rating_weights[rating.stars() as usize]
+    }
+
+    pub fn sources(&self) -> &Vec<Source> {
+        &self.sources
+    }
+
+    pub fn sources_mut(&mut self) -> &mut Vec<Source> {
+        &mut self.sources
+    }
+
+    pub fn scan_depth(&self) -> usize {
+        self.scan_depth
+    }
+
+    pub fn folder_tags(&self) -> bool {
+        self.folder_tags
+    }
+
+    pub fn import_metadata(&self) -> bool {
+        self.import_metadata
+    }
+
+    pub fn detection(&self) -> Detection {
+        self.detection
+    }
+
+    pub fn detection_confidence(&self) -> f32 {
+        self.detection_confidence
+    }
+
+    pub fn suggest_tags(&self) -> bool {
+        self.suggest_tags
+    }
+
+    pub fn sidecar_precedence(&self) -> Precedence {
+        self.sidecar_precedence
+    }
+
+    pub fn storage(&self) -> Backend {
+        self.storage
+    }
+
+    pub fn pause_on_battery(&self) -> bool {
+        self.pause_on_battery
+    }
+
+    pub fn pause_marker(&self) -> Option<&Path> {
+        self.pause_marker.as_deref()
+    }
+
+    pub fn schedule(&self) -> &Vec<ScheduleRule> {
+        &self.schedule
+    }
+
+    pub fn schedule_mut(&mut self) -> &mut Vec<ScheduleRule> {
+        &mut self.schedule
+    }
 }
 
 
@@ -42,6 +172,23 @@ pub fn edit_settings(config: &mut Config) -> io::Result<()> {
         .with_prompt("Edit settings")
         .items(&format_items(vec![
             &format!("Set refresh interval [{} mins]", config.interval_mins()),
+            &format!("Set refresh mode [{}]", config.refresh_mode),
+            &format!("Set maximum dwell time [{}]", config.max_dwell_mins()),
+            &format!("Set weather change tolerance [{} polls, weight below {}]", 
+                config.stable_polls, config.replace_below_weight),
+            &format!("Set rating weights [{}]", format_weights(&config.rating_weights)),
+            &format!("Edit schedule rules [{}]", config.schedule.len()),
+            &format!("Edit wallpaper sources [{}]", config.sources.len()),
+            &format!("Set subfolder scan depth [{}]", config.scan_depth),
+            &format!("Tag new wallpapers by subfolder [{}]", if config.folder_tags { "on" } else { "off" }),
+            &format!("Tag new wallpapers from filenames and metadata [{}]", if config.import_metadata { "on" } else { "off" }),
+            &format!("Set day/night detection [{}, {:.0}% confidence]", 
+                config.detection, config.detection_confidence * 100.0),
+            &format!("Suggest tags from image colours [{}]", if config.suggest_tags { "on" } else { "off" }),
+            &format!("Set sidecar file precedence [{}]", config.sidecar_precedence),
+            &format!("Set storage backend [{}]", config.storage),
+            &format!("Set automatic pause [{}]", format_automatic_pause(config)),
+            &format!("Prune thumbnail cache [{:.1} MB]", thumbnails::cache_size().unwrap_or(0) as f32 / 1_000_000.0),
             "Restore default settings",
             "Back",
         ]))
@@ -52,8 +199,23 @@ pub fn edit_settings(config: &mut Config) -> io::Result<()> {
 
     match choice {
         Some(0) => set_interval(config),
-        Some(1) => *config = Config::default(),
-        None | Some(2) => return Ok(()),
+        Some(1) => set_refresh_mode(config),
+        Some(2) => set_max_dwell(config),
+        Some(3) => set_tolerance(config),
+        Some(4) => set_rating_weights(config),
+        Some(5) => schedule::edit_schedule(config),
+        Some(6) => sources::edit_sources(config),
+        Some(7) => set_scan_depth(config),
+        Some(8) => config.folder_tags = !config.folder_tags,
+        Some(9) => config.import_metadata = !config.import_metadata,
+        Some(10) => set_detection(config),
+        Some(11) => config.suggest_tags = !config.suggest_tags,
+        Some(12) => set_sidecar_precedence(config),
+        Some(13) => set_storage(config)?,
+        Some(14) => set_automatic_pause(config),
+        Some(15) => prune_cache(config),
+        Some(16) => *config = Config::default(),
+        None | Some(17) => return Ok(()),
         _ => unreachable!()
     };
 
@@ -87,20 +249,291 @@ fn set_interval(config: &mut Config) {
     Term::stdout().clear_last_lines(1).unwrap();
 }
 
+/* Handle input for refresh mode */
+fn set_refresh_mode(config: &mut Config) {
+    let modes: Vec<RefreshMode> = RefreshMode::iter().collect();
+
+    let choice = Select::new()
+        .with_prompt("Set refresh mode")
+        .items(&format_items(vec![
+            "Interval (new wallpaper every refresh)",
+            "Weather change (new wallpaper only when the weather changes)",
+        ]))
+        .default(modes.iter().position(|mode| *mode == config.refresh_mode).unwrap())
+        .report(false)
+        .interact_opt()
+        .unwrap();
+
+    if let Some(index) = choice {
+        config.refresh_mode = modes[index];
+    }
+}
+
+/* Handle input for maximum dwell time */
+fn set_max_dwell(config: &mut Config) {
+    let prompt = format!("Set maximum dwell time, 0 for none [{}]", config.max_dwell_mins());
+
+    /* Ensure input is non-negative */
+    let validator = |x: &f32| -> Result<(), &str> {
+        if *x >= 0.0 { 
+            Ok(()) 
+        } else { 
+            Err("Cannot be negative") 
+        }
+    };
+
+    /* Get dwell input in minutes */
+    let mins = Input::<f32>::new()
+        .with_prompt(prompt)
+        .validate_with(validator)
+        .interact()
+        .unwrap();
+
+    /* Update maximum dwell (in millis) */
+    config.max_dwell = (mins > 0.0).then_some((mins * 60.0 * 1000.0) as u64);
+
+    /* Clear input */
+    Term::stdout().clear_last_lines(1).unwrap();
+}
+
+/* Handle input for how long changed weather must persist before the wallpaper is replaced */
+fn set_tolerance(config: &mut Config) {
+    config.stable_polls = Input::<u32>::new()
+        .with_prompt("Polls changed weather must persist for")
+        .with_initial_text(config.stable_polls.to_string())
+        .validate_with(|x: &u32| (*x > 0).then_some(()).ok_or("Must be at least 1"))
+        .interact_text()
+        .unwrap();
+
+    config.replace_below_weight = Input::<usize>::new()
+        .with_prompt("Replace immediately if current wallpaper's weight is below (0 disables)")
+        .with_initial_text(config.replace_below_weight.to_string())
+        .interact_text()
+        .unwrap();
+
+    /* Clear input */
+    Term::stdout().clear_last_lines(2).unwrap();
+}
+
+/* Handle input for subfolder scan depth */
+fn set_scan_depth(config: &mut Config) {
+    config.scan_depth = Input::<usize>::new()
+        .with_prompt("Set subfolder scan depth (0 for top level only)")
+        .with_initial_text(config.scan_depth.to_string())
+        .interact_text()
+        .unwrap();
+
+    /* Clear input */
+    Term::stdout().clear_last_lines(1).unwrap();
+}
+
+/* Handle input for day/night detection of new wallpapers */
+fn set_detection(config: &mut Config) {
+    let modes: Vec<Detection> = Detection::iter().collect();
+
+    let choice = Select::new()
+        .with_prompt("Set day/night detection for new wallpapers")
+        .items(&format_items(vec![
+            "Off",
+            "Suggest (review in tag editor)",
+            "Apply (when confident enough)",
+        ]))
+        .default(modes.iter().position(|mode| *mode == config.detection).unwrap())
+        .report(false)
+        .interact_opt()
+        .unwrap();
+
+    let Some(index) = choice else {
+        return;
+    };
+
+    config.detection = modes[index];
+
+    if config.detection == Detection::Apply {
+        let percent = Input::<f32>::new()
+            .with_prompt("Confidence needed to apply (%)")
+            .with_initial_text(format!("{:.0}", config.detection_confidence * 100.0))
+            .validate_with(|x: &f32| (0.0..=100.0).contains(x).then_some(()).ok_or("Must be between 0 and 100"))
+            .interact_text()
+            .unwrap();
+
+        config.detection_confidence = percent / 100.0;
+
+        /* Clear input */
+        Term::stdout().clear_last_lines(1).unwrap();
+    }
+}
+
+/* Handle input for whether sidecar files or the central tags file wins */
+fn set_sidecar_precedence(config: &mut Config) {
+    let modes: Vec<Precedence> = Precedence::iter().collect();
+
+    let choice = Select::new()
+        .with_prompt("Set sidecar file precedence")
+        .items(&format_items(vec![
+            "Central (sidecars only tag new wallpapers)",
+            "Sidecar (sidecars override saved tags, and edits are written back to them)",
+        ]))
+        .default(modes.iter().position(|mode| *mode == config.sidecar_precedence).unwrap())
+        .report(false)
+        .interact_opt()
+        .unwrap();
+
+    if let Some(index) = choice {
+        config.sidecar_precedence = modes[index];
+    }
+}
+
+/* Handle input for storage backend, copying saved data to the new backend */
+fn set_storage(config: &mut Config) -> io::Result<()> {
+    let backends: Vec<Backend> = Backend::iter().collect();
+
+    let choice = Select::new()
+        .with_prompt("Set storage backend")
+        .items(&format_items(vec![
+            "JSON (files in the data directory)",
+            "SQLite (database, faster for large libraries)",
+        ]))
+        .default(backends.iter().position(|backend| *backend == config.storage).unwrap())
+        .report(false)
+        .interact_opt()
+        .unwrap();
+
+    let Some(backend) = choice.map(|index| backends[index]) else {
+        return Ok(());
+    };
+
+    if backend != config.storage {
+        let from = storage::open(config)?;
+        config.storage = backend;
+
+        storage::copy(from.as_ref(), storage::open(config)?.as_mut())?;
+    }
+
+    Ok(())
+}
+
+/* Handle input for the conditions that pause automatic changes */
+fn set_automatic_pause(config: &mut Config) {
+    let choice = Select::new()
+        .with_prompt("Pause automatic wallpaper changes")
+        .items(&format_items(vec![
+            format!("On battery [{}]", if config.pause_on_battery { "on" } else { "off" }),
+            format!("While marker file exists [{}]", config.pause_marker.as_ref().map_or(String::from("none"), |path| path.display().to_string())),
+        ]))
+        .default(0)
+        .report(false)
+        .interact_opt()
+        .unwrap();
+
+    match choice {
+        Some(0) => config.pause_on_battery = !config.pause_on_battery,
+        Some(1) => {
+            let input = Input::<String>::new()
+                .with_prompt("Marker file (leave empty for none)")
+                .with_initial_text(config.pause_marker.as_ref().map_or(String::new(), |path| path.display().to_string()))
+                .allow_empty(true)
+                .interact_text()
+                .unwrap();
+
+            config.pause_marker = (!input.trim().is_empty()).then(|| PathBuf::from(input.trim()));
+        },
+        _ => (),
+    }
+}
+
+fn format_automatic_pause(config: &Config) -> String {
+    let mut conditions = Vec::new();
+
+    if config.pause_on_battery {
+        conditions.push(String::from("on battery"));
+    }
+
+    if let Some(marker) = &config.pause_marker {
+        conditions.push(format!("while {} exists", marker.display()));
+    }
+
+    if conditions.is_empty() {
+        String::from("off")
+    } else {
+        conditions.join(", ")
+    }
+}
+
+/* Delete cached thumbnails of wallpapers that have been removed or changed */
+fn prune_cache(config: &Config) {
+    /* Rescan first so thumbnails of wallpapers in disabled sources are kept too */
+    files::load_all_wallpapers(config).unwrap();
+
+    let term = Term::stdout();
+    term.clear_screen().unwrap();
+
+    let saved = storage::open(config)
+        .and_then(|store| store.load_wallpapers())
+        .unwrap_or_default();
+
+    match thumbnails::prune_thumbnails(&saved) {
+        Ok((removed, freed)) => println!("Removed {removed} thumbnails ({:.1} MB)", freed as f32 / 1_000_000.0),
+        Err(err) => println!("Could not prune thumbnails: {err}"),
+    }
+
+    println!("\nPress enter to return");
+    term.read_line().unwrap();
+    term.clear_screen().unwrap();
+}
+
+/* Handle input for the multiplier applied to each star rating */
+fn set_rating_weights(config: &mut Config) {
+    let prompt = format!("Set weights for 0-{} stars [{}]", 
+        rating::MAX_RATING, format_weights(&config.rating_weights));
+
+    let input = Input::<String>::new()
+        .with_prompt(prompt)
+        .with_initial_text(format_weights(&config.rating_weights))
+        .validate_with(|input: &String| parse_weights(input).map(|_| ()))
+        .interact_text()
+        .unwrap();
+
+    config.rating_weights = parse_weights(&input).unwrap();
+
+    /* Clear input */
+    Term::stdout().clear_last_lines(1).unwrap();
+}
+
+/* Parse comma-separated weight for each star rating */
+fn parse_weights(input: &str) -> Result<[usize; rating::MAX_RATING as usize + 1], &'static str> {
+    let weights = input.split(',')
+        .map(|weight| weight.trim().parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|_| "Weights must be non-negative integers")?;
+
+    weights.try_into()
+        .map_err(|_| "Provide one weight for each rating from 0 to 5 stars")
+}
+
+fn format_weights(weights: &[usize]) -> String {
+    weights.iter()
+        .map(usize::to_string)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
 
 /* Save settings to .json file */
-fn save_settings(config: &Config) -> io::Result<()> {
-    fs::write(saved_settings_path()?, serde_json::to_string_pretty(config)?)
+pub fn save_settings(config: &Config) -> io::Result<()> {
+    schema::save(&saved_settings_path()?, config, &MIGRATIONS)?;
+    Ok(())
 }
 
 /* Load settings from .json file */
 pub fn load_settings() -> io::Result<Config> {
-    let contents = fs::read_to_string(saved_settings_path()?)?;
-    let config = serde_json::from_str(&contents)?;
+    let mut config: Config = schema::load(&saved_settings_path()?, &MIGRATIONS)?;
+    sources::make_names_unique(&mut config.sources);
+
     Ok(config)
 }
 
 /* Helper function to get path to file of saved settings */
 fn saved_settings_path() -> io::Result<PathBuf> {
-    files::data_path().and_then(|path| Ok(path.join(SAVED_SETTINGS_FILE)))
+    files::data_path().map(|path| path.join(SAVED_SETTINGS_FILE))
 }
@@ -0,0 +1,174 @@
+use std::{collections::HashSet, fs, io, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumIter};
+
+use crate::{files, rating::Rating, wallpaper::Wallpaper, weather::{self, DayNight, WeatherTag}};
+
+/* Sidecar file suffixes appended to the image's file name (e.g. "image.jpg.weather.json"), the first being used for new sidecars */
+const SIDECAR_SUFFIXES: [&str; 3] = [".weather.json", ".weather.yaml", ".weather.yml"];
+
+
+/* Whether sidecar files or the central tags file wins when both describe a wallpaper */
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Display, EnumIter)]
+pub enum Precedence {
+    /* Sidecars only tag newly found wallpapers, after which the central tags file is used */
+    #[default]
+    Central,
+    /* Sidecars override the central tags file on every load, and edits are written back to them */
+    Sidecar,
+}
+
+
+/* Tags, day/night and rating stored next to an image, each optional (missing fields are left to the central tags file) */
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Sidecar {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>, /* Tag names or keywords, e.g. "Rain" or "drizzle" */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    time: Option<DayNight>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rating: Option<Rating>,
+}
+
+impl Sidecar {
+    /* Describe all of wallpaper's tags, day/night and rating */
+    fn from_wallpaper(wallpaper: &Wallpaper) -> Sidecar {
+        Sidecar {
+            tags: Some(weather::tag_names(wallpaper.weather.tags())),
+            time: Some(DayNight::from_is_day(wallpaper.weather.is_day())),
+            rating: Some(wallpaper.rating()),
+        }
+    }
+
+    fn tags(&self) -> Option<HashSet<WeatherTag>> {
+        self.tags.as_ref().map(|keywords| keywords.iter()
+            .flat_map(|keyword| WeatherTag::from_keyword(keyword))
+            .collect())
+    }
+
+    /* Overwrite wallpaper's fields with those present in the sidecar, replacing any suggestions */
+    fn apply(&self, wallpaper: &mut Wallpaper) {
+        if let Some(tags) = self.tags() {
+            wallpaper.weather.set_tags(tags);
+            wallpaper.set_suggested_tags(HashSet::new());
+        }
+
+        if let Some(time) = self.time {
+            wallpaper.weather.set_is_day(time.is_day());
+            wallpaper.clear_suggestion();
+        }
+
+        if let Some(rating) = self.rating {
+            wallpaper.set_rating(rating);
+        }
+    }
+
+    /* Update the fields present in the sidecar from wallpaper, returning whether any changed */
+    fn update(&mut self, wallpaper: &Wallpaper) -> bool {
+        let mut changed = false;
+
+        if self.tags().is_some_and(|tags| tags != *wallpaper.weather.tags()) {
+            self.tags = Some(weather::tag_names(wallpaper.weather.tags()));
+            changed = true;
+        }
+
+        let time = DayNight::from_is_day(wallpaper.weather.is_day());
+
+        if self.time.is_some_and(|current| current != time) {
+            self.time = Some(time);
+            changed = true;
+        }
+
+        if self.rating.is_some_and(|current| current != wallpaper.rating()) {
+            self.rating = Some(wallpaper.rating());
+            changed = true;
+        }
+
+        changed
+    }
+}
+
+
+/* Apply the image's sidecar (if any) to wallpaper, returning whether one was found */
+pub fn apply_sidecar(wallpaper: &mut Wallpaper) -> bool {
+    match read_sidecar(wallpaper.as_ref()) {
+        Some(sidecar) => {
+            sidecar.apply(wallpaper);
+            true
+        },
+        None => false,
+    }
+}
+
+/* Write edits back to the fields held by existing sidecars, returning the wallpapers whose sidecar could not be written */
+pub fn update_sidecars<'a>(wallpapers: impl IntoIterator<Item = &'a Wallpaper>) -> Vec<(PathBuf, io::Error)> {
+    let mut failed = Vec::new();
+
+    for wallpaper in wallpapers {
+        if let Some(mut sidecar) = read_sidecar(wallpaper.as_ref()) {
+            if sidecar.update(wallpaper) {
+                if let Err(err) = write_sidecar(wallpaper.as_ref(), &sidecar) {
+                    failed.push((wallpaper.as_ref().to_path_buf(), err));
+                }
+            }
+        }
+    }
+
+    failed
+}
+
+/* Write sidecars holding all tags, day/night and rating of every wallpaper, returning how many were written */
+pub fn export_sidecars<'a>(wallpapers: impl IntoIterator<Item = &'a Wallpaper>) -> io::Result<usize> {
+    let mut written = 0;
+
+    for wallpaper in wallpapers {
+        write_sidecar(wallpaper.as_ref(), &Sidecar::from_wallpaper(wallpaper))?;
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+
+/* Get image's existing sidecar file */
+fn sidecar_path(image: &Path) -> Option<PathBuf> {
+    SIDECAR_SUFFIXES.iter()
+        .map(|suffix| with_suffix(image, suffix))
+        .find(|path| path.is_file())
+}
+
+fn with_suffix(image: &Path, suffix: &str) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/* Read image's sidecar as JSON or YAML (by extension), ignoring unreadable sidecars */
+fn read_sidecar(image: &Path) -> Option<Sidecar> {
+    let path = sidecar_path(image)?;
+    let contents = fs::read_to_string(&path).ok()?;
+
+    if is_yaml(&path) {
+        serde_yaml::from_str(&contents).ok()
+    } else {
+        serde_json::from_str(&contents).ok()
+    }
+}
+
+/* Write sidecar, keeping the format of an existing sidecar or creating a JSON one */
+fn write_sidecar(image: &Path, sidecar: &Sidecar) -> io::Result<()> {
+    let path = sidecar_path(image).unwrap_or_else(|| with_suffix(image, SIDECAR_SUFFIXES[0]));
+
+    let contents = if is_yaml(&path) {
+        serde_yaml::to_string(sidecar).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+    } else {
+        serde_json::to_string_pretty(sidecar)?
+    };
+
+    files::write_atomic(&path, contents)
+}
+
+fn is_yaml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml")
+}
@@ -0,0 +1,341 @@
+use std::{fmt::{self, Display}, path::{Path, PathBuf}};
+
+use colored::Colorize;
+use console::Term;
+use dialoguer::{Input, Select};
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use crate::{files, format_items, settings::Config};
+
+
+/* Directory to load wallpapers from */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Source {
+    path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>, /* Identifies the source in saved tags, so they resolve on other machines (directory name if unset) */
+    #[serde(default)]
+    include: Vec<String>, /* Glob patterns files must match one of (any file if empty) */
+    #[serde(default)]
+    exclude: Vec<String>, /* Glob patterns files must not match */
+    #[serde(default = "enabled_default")]
+    enabled: bool,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+/* Default wallpaper directory (nested in Picture directory) */
+impl Default for Source {
+    fn default() -> Self {
+        Source::new(files::wallpapers_path().unwrap_or_default())
+    }
+}
+
+/* Print path, patterns and whether source is enabled */
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display().to_string();
+
+        write!(f, "{}", if self.enabled { path.bold() } else { path.dimmed() })?;
+
+        if self.name.is_some() {
+            write!(f, " name: {}", self.name())?;
+        }
+
+        if !self.include.is_empty() {
+            write!(f, " include: {}", self.include.join(", "))?;
+        }
+
+        if !self.exclude.is_empty() {
+            write!(f, " exclude: {}", self.exclude.join(", "))?;
+        }
+
+        if !self.enabled {
+            write!(f, " {}", "(disabled)".dimmed())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Source {
+    pub fn new(path: PathBuf) -> Source {
+        Source {
+            path,
+            name: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            enabled: true,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.directory_name())
+    }
+
+    fn directory_name(&self) -> String {
+        self.path.file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().into_owned())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /* Check file (relative to source directory) matches include and exclude patterns */
+    pub fn matches(&self, file_path: &Path) -> bool {
+        let relative = file_path.strip_prefix(&self.path).unwrap_or(file_path);
+
+        let matches_any = |patterns: &Vec<String>| patterns.iter()
+            .filter_map(|pattern| Pattern::new(pattern).ok())
+            .any(|pattern| pattern.matches_path(relative));
+
+        (self.include.is_empty() || matches_any(&self.include)) && !matches_any(&self.exclude)
+    }
+}
+
+
+/* Rename sources whose name is already taken "<name>-2", "<name>-3"... so saved paths resolve to a single source.
+ * Names set by the user are kept over directory names */
+pub fn make_names_unique(sources: &mut [Source]) {
+    let mut taken: Vec<String> = Vec::new();
+
+    let mut sources: Vec<&mut Source> = sources.iter_mut().collect();
+    sources.sort_by_key(|source| source.name.is_none());
+
+    for source in sources {
+        let name = source.name();
+
+        if taken.contains(&name) {
+            let unique = (2..)
+                .map(|number| format!("{name}-{number}"))
+                .find(|candidate| !taken.contains(candidate))
+                .unwrap();
+
+            source.name = Some(unique);
+        }
+
+        taken.push(source.name());
+    }
+}
+
+/* Get name of the (innermost) source containing path, and the path relative to it with '/' separators, for saving */
+pub fn to_relative(sources: &[Source], path: &Path) -> Option<(String, String)> {
+    let (source, relative) = sources.iter()
+        .filter_map(|source| Some((source, path.strip_prefix(&source.path).ok()?)))
+        .min_by_key(|(_, relative)| relative.components().count())?;
+
+    let relative = relative.iter()
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    Some((source.name(), relative))
+}
+
+/* Get absolute path from path saved relative to the named source, if it is configured */
+pub fn to_absolute(sources: &[Source], name: &str, relative: &str) -> Option<PathBuf> {
+    let source = sources.iter().find(|source| source.name() == name)?;
+
+    Some(relative.split('/').fold(source.path.clone(), |path, part| path.join(part)))
+}
+
+
+/* Add, remove and edit wallpaper sources */
+pub fn edit_sources(config: &mut Config) {
+    loop {
+        let mut items: Vec<String> = config.sources().iter()
+            .map(Source::to_string)
+            .collect();
+
+        items.push(String::from("Add source"));
+        items.push(String::from("Back"));
+
+        let choice = Select::new()
+            .with_prompt("Wallpaper sources")
+            .items(&format_items(items))
+            .default(config.sources().len())
+            .report(false)
+            .interact_opt()
+            .unwrap();
+
+        let sources = config.sources_mut();
+
+        match choice {
+            Some(index) if index < sources.len() => {
+                let others: Vec<String> = sources.iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != index)
+                    .map(|(_, source)| source.name())
+                    .collect();
+
+                if !edit_source(&mut sources[index], &others) {
+                    sources.remove(index);
+                }
+            },
+            Some(index) if index == sources.len() => {
+                let path = Input::<String>::new()
+                    .with_prompt("Directory path")
+                    .validate_with(|input: &String|
+                        Path::new(input).is_dir()
+                            .then_some(())
+                            .ok_or("Directory does not exist")
+                    )
+                    .interact_text()
+                    .unwrap();
+
+                Term::stdout().clear_last_lines(1).unwrap();
+
+                sources.push(Source::new(PathBuf::from(path)));
+                make_names_unique(sources);
+            },
+            _ => return, /* Back */
+        }
+    }
+}
+
+/* Edit a source, keeping its name different from the others (returns false if the source should be removed) */
+fn edit_source(source: &mut Source, others: &[String]) -> bool {
+    loop {
+        let choice = Select::new()
+            .with_prompt(source.to_string())
+            .items(&format_items(vec![
+                String::from(if source.enabled { "Disable" } else { "Enable" }),
+                format!("Set name [{}]", source.name()),
+                String::from("Set include patterns"),
+                String::from("Set exclude patterns"),
+                String::from("Remove source"),
+                String::from("Back"),
+            ]))
+            .default(0)
+            .report(false)
+            .interact_opt()
+            .unwrap();
+
+        match choice {
+            Some(0) => source.enabled = !source.enabled,
+            Some(1) => source.name = name_input(source, others),
+            Some(2) => source.include = patterns_input("Include patterns, comma-separated (e.g. *.png, landscapes/*)", &source.include),
+            Some(3) => source.exclude = patterns_input("Exclude patterns, comma-separated", &source.exclude),
+            Some(4) => return false,
+            _ => return true, /* Back */
+        }
+    }
+}
+
+/* Handle input for source name (empty for the directory name), rejecting names used by other sources */
+fn name_input(source: &Source, others: &[String]) -> Option<String> {
+    let directory_name = source.directory_name();
+
+    let input = Input::<String>::new()
+        .with_prompt("Name (use the same name on each machine, empty for directory name)")
+        .with_initial_text(source.name.clone().unwrap_or_default())
+        .allow_empty(true)
+        .validate_with(|input: &String| {
+            let name = match input.trim() {
+                "" => &directory_name,
+                name => name,
+            };
+
+            (!others.iter().any(|other| other == name))
+                .then_some(())
+                .ok_or("Name is used by another source")
+        })
+        .interact_text()
+        .unwrap();
+
+    Term::stdout().clear_last_lines(1).unwrap();
+
+    let name = input.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/* Handle input for comma-separated glob patterns */
+fn patterns_input(prompt: &str, current: &[String]) -> Vec<String> {
+    let input = Input::<String>::new()
+        .with_prompt(prompt)
+        .with_initial_text(current.join(", "))
+        .allow_empty(true)
+        .validate_with(|input: &String|
+            parse_patterns(input).iter()
+                .all(|pattern| Pattern::new(pattern).is_ok())
+                .then_some(())
+                .ok_or("Invalid glob pattern")
+        )
+        .interact_text()
+        .unwrap();
+
+    Term::stdout().clear_last_lines(1).unwrap();
+
+    parse_patterns(&input)
+}
+
+fn parse_patterns(input: &str) -> Vec<String> {
+    input.split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(path: &str, name: Option<&str>) -> Source {
+        Source { name: name.map(String::from), ..Source::new(PathBuf::from(path)) }
+    }
+
+    #[test]
+    fn relative_path_round_trips() {
+        let sources = [source("/home/user/Pictures/weather_wallpapers", None)];
+        let path = Path::new("/home/user/Pictures/weather_wallpapers/rain/heavy.png");
+
+        let (name, relative) = to_relative(&sources, path).unwrap();
+        assert_eq!((name.as_str(), relative.as_str()), ("weather_wallpapers", "rain/heavy.png"));
+        assert_eq!(to_absolute(&sources, &name, &relative).as_deref(), Some(path));
+    }
+
+    #[test]
+    fn innermost_source_is_used() {
+        let sources = [source("/pictures", None), source("/pictures/phone", Some("phone"))];
+
+        let relative = to_relative(&sources, Path::new("/pictures/phone/2024/fog.png"));
+        assert_eq!(relative, Some((String::from("phone"), String::from("2024/fog.png"))));
+    }
+
+    #[test]
+    fn paths_outside_sources_stay_absolute() {
+        let sources = [source("/pictures", None)];
+        assert_eq!(to_relative(&sources, Path::new("/downloads/sun.png")), None);
+    }
+
+    #[test]
+    fn duplicate_names_get_suffixes() {
+        let mut sources = [
+            source("/home/user/wallpapers", None),
+            source("/mnt/backup/wallpapers", None),
+            source("/mnt/phone", Some("wallpapers-2")),
+            source("/mnt/other/wallpapers", None),
+        ];
+
+        make_names_unique(&mut sources);
+
+        let names: Vec<String> = sources.iter().map(Source::name).collect();
+        assert_eq!(names, ["wallpapers", "wallpapers-3", "wallpapers-2", "wallpapers-4"]);
+    }
+
+    #[test]
+    fn unknown_source_is_unresolved() {
+        let sources = [source("/pictures", None)];
+        assert_eq!(to_absolute(&sources, "laptop", "sun.png"), None);
+    }
+}
@@ -0,0 +1,541 @@
+use std::{cell::RefCell, collections::{BTreeSet, HashMap, HashSet}, fs::OpenOptions, io::{self, Write}, path::{Path, PathBuf}};
+
+use chrono::Local;
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter};
+
+use crate::{collections, files, rating::Rating, settings::Config, sources::{self, Source}, wallpaper::{self, Wallpaper}, warnings, weather::{Weather, WeatherTag}};
+
+const DATABASE_FILE: &str = "wallpapers.db";
+const HISTORY_FILE: &str = "history.jsonl";
+
+/* Migrations from each version of the database (stored as its user_version) to the next */
+const DATABASE_MIGRATIONS: [&str; 2] = [
+    /* 0 -> 1 */
+    "
+    CREATE TABLE IF NOT EXISTS wallpapers (
+        path TEXT PRIMARY KEY,
+        filename TEXT NOT NULL,
+        is_day INTEGER,
+        rating INTEGER NOT NULL,
+        hash TEXT,
+        suggestion TEXT,
+        suggested_tags TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS tags (
+        path TEXT NOT NULL REFERENCES wallpapers(path) ON DELETE CASCADE,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (path, tag)
+    );
+
+    CREATE INDEX IF NOT EXISTS tags_by_tag ON tags(tag);
+
+    CREATE TABLE IF NOT EXISTS collections (
+        name TEXT PRIMARY KEY,
+        position INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS collection_members (
+        path TEXT NOT NULL REFERENCES wallpapers(path) ON DELETE CASCADE,
+        collection TEXT NOT NULL,
+        PRIMARY KEY (path, collection)
+    );
+
+    CREATE TABLE IF NOT EXISTS selections (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        selected_at TEXT NOT NULL,
+        weather TEXT
+    );
+
+    CREATE INDEX IF NOT EXISTS selections_by_path ON selections(path);
+    ",
+
+    /* 1 -> 2: paths are relative to the named source ('' for absolute paths, as all existing paths are) */
+    "
+    CREATE TABLE wallpapers_v2 (
+        source TEXT NOT NULL DEFAULT '',
+        path TEXT NOT NULL,
+        filename TEXT NOT NULL,
+        is_day INTEGER,
+        rating INTEGER NOT NULL,
+        hash TEXT,
+        suggestion TEXT,
+        suggested_tags TEXT,
+        PRIMARY KEY (source, path)
+    );
+
+    CREATE TABLE tags_v2 (
+        source TEXT NOT NULL,
+        path TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (source, path, tag),
+        FOREIGN KEY (source, path) REFERENCES wallpapers(source, path) ON DELETE CASCADE
+    );
+
+    CREATE TABLE collection_members_v2 (
+        source TEXT NOT NULL,
+        path TEXT NOT NULL,
+        collection TEXT NOT NULL,
+        PRIMARY KEY (source, path, collection),
+        FOREIGN KEY (source, path) REFERENCES wallpapers(source, path) ON DELETE CASCADE
+    );
+
+    INSERT INTO wallpapers_v2 SELECT '', path, filename, is_day, rating, hash, suggestion, suggested_tags FROM wallpapers;
+    INSERT INTO tags_v2 SELECT '', path, tag FROM tags;
+    INSERT INTO collection_members_v2 SELECT '', path, collection FROM collection_members;
+
+    DROP TABLE tags;
+    DROP TABLE collection_members;
+    DROP TABLE wallpapers;
+
+    ALTER TABLE wallpapers_v2 RENAME TO wallpapers;
+    ALTER TABLE tags_v2 RENAME TO tags;
+    ALTER TABLE collection_members_v2 RENAME TO collection_members;
+
+    CREATE INDEX tags_by_tag ON tags(tag);
+    ",
+];
+
+
+/* Where wallpapers, collections and selection history are stored */
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Display, EnumIter)]
+pub enum Backend {
+    /* JSON files in the data directory */
+    #[default]
+    #[strum(to_string = "JSON")]
+    Json,
+    /* Embedded database, faster for large libraries */
+    #[strum(to_string = "SQLite")]
+    Sqlite,
+}
+
+
+/* Saved wallpapers, collections and selection history */
+pub trait Storage {
+    fn load_wallpapers(&self) -> io::Result<HashSet<Wallpaper>>;
+
+    /* Replace all saved wallpapers */
+    fn save_wallpapers(&mut self, wallpapers: &HashSet<Wallpaper>) -> io::Result<()>;
+
+    fn load_collections(&self) -> io::Result<Vec<String>>;
+
+    fn save_collections(&mut self, collections: &[String]) -> io::Result<()>;
+
+    /* Paths of saved wallpapers with tag */
+    fn tagged(&self, tag: &WeatherTag) -> io::Result<Vec<PathBuf>>;
+
+    /* Record wallpaper being set, along with the weather it was chosen for */
+    fn record_selection(&mut self, wallpaper: &Wallpaper, weather: Option<&Weather>) -> io::Result<()>;
+}
+
+/* Open the configured storage backend */
+pub fn open(config: &Config) -> io::Result<Box<dyn Storage>> {
+    let sources = config.sources().clone();
+
+    Ok(match config.storage() {
+        Backend::Json => Box::new(JsonStorage { sources }),
+        Backend::Sqlite => Box::new(SqliteStorage::open(sources)?),
+    })
+}
+
+/* Copy all wallpapers and collections between backends (selection history is not copied) */
+pub fn copy(from: &dyn Storage, to: &mut dyn Storage) -> io::Result<()> {
+    to.save_wallpapers(&from.load_wallpapers().unwrap_or_default())?;
+    to.save_collections(&from.load_collections().unwrap_or_default())
+}
+
+
+/* Selection history entry */
+#[derive(Serialize)]
+struct Selection<'a> {
+    path: &'a Path,
+    selected_at: String,
+    weather: Option<&'a Weather>,
+}
+
+
+/* Versioned JSON files for wallpapers and collections, with history appended to a JSON lines file */
+struct JsonStorage {
+    sources: Vec<Source>, /* Sources saved paths are relative to */
+}
+
+impl Storage for JsonStorage {
+    fn load_wallpapers(&self) -> io::Result<HashSet<Wallpaper>> {
+        wallpaper::load_wallpapers(&self.sources)
+    }
+
+    fn save_wallpapers(&mut self, wallpapers: &HashSet<Wallpaper>) -> io::Result<()> {
+        wallpaper::save_wallpapers(wallpapers, &self.sources)
+    }
+
+    fn load_collections(&self) -> io::Result<Vec<String>> {
+        collections::load_collections()
+    }
+
+    fn save_collections(&mut self, collections: &[String]) -> io::Result<()> {
+        collections::save_collections(collections)
+    }
+
+    fn tagged(&self, tag: &WeatherTag) -> io::Result<Vec<PathBuf>> {
+        Ok(self.load_wallpapers()?
+            .into_iter()
+            .filter(|wallpaper| wallpaper.weather.tags().contains(tag))
+            .map(|wallpaper| wallpaper.as_ref().to_path_buf())
+            .collect())
+    }
+
+    fn record_selection(&mut self, wallpaper: &Wallpaper, weather: Option<&Weather>) -> io::Result<()> {
+        let selection = Selection {
+            path: wallpaper.as_ref(),
+            selected_at: Local::now().to_rfc3339(),
+            weather,
+        };
+
+        let mut history = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(files::data_path()?.join(HISTORY_FILE))?;
+
+        writeln!(history, "{}", serde_json::to_string(&selection)?)
+    }
+}
+
+
+/* Saved source name ('' for absolute paths) and path of a wallpaper */
+type Key = (String, String);
+
+/* Saved fields of a wallpaper, compared directly to find which wallpapers need writing */
+#[derive(PartialEq)]
+struct Row {
+    filename: String,
+    is_day: Option<bool>,
+    rating: u8,
+    hash: Option<String>,
+    suggestion: Option<String>, /* As JSON */
+    suggested_tags: BTreeSet<String>,
+    tags: BTreeSet<String>,
+    collections: BTreeSet<String>,
+}
+
+impl Row {
+    fn new((_, path): &Key, wallpaper: &Wallpaper) -> Row {
+        Row {
+            filename: wallpaper::filename(Path::new(path)),
+            is_day: wallpaper.weather.is_day(),
+            rating: wallpaper.rating().stars(),
+            hash: wallpaper.hash().map(String::from),
+            suggestion: wallpaper.suggestion().and_then(|suggestion| serde_json::to_string(suggestion).ok()),
+            suggested_tags: wallpaper.suggested_tags().iter().map(tag_name).collect(),
+            tags: wallpaper.weather.tags().iter().map(tag_name).collect(),
+            collections: wallpaper.collections().clone(),
+        }
+    }
+
+    /* Wallpaper from its saved fields, failing if a tag is not recognised */
+    fn to_wallpaper(&self, (source, path): &Key, sources: &[Source]) -> Result<Wallpaper, String> {
+        let mut weather = Weather::default();
+        weather.set_tags(parse_tags(&self.tags)?);
+        weather.set_is_day(self.is_day);
+
+        let source = (!source.is_empty()).then(|| source.clone());
+        let mut wallpaper = Wallpaper::saved(PathBuf::from(path), source, weather, Rating::new(self.rating), self.hash.clone());
+
+        wallpaper.set_collections(self.collections.clone());
+        wallpaper.set_suggestion(self.suggestion.as_deref().and_then(|text| serde_json::from_str(text).ok()));
+        wallpaper.set_suggested_tags(parse_tags(&self.suggested_tags)?);
+        wallpaper.resolve(sources);
+
+        Ok(wallpaper)
+    }
+}
+
+/* SQLite database, with tags and collection membership in indexed tables */
+struct SqliteStorage {
+    connection: Connection,
+    sources: Vec<Source>, /* Sources saved paths are relative to */
+    loaded: RefCell<Option<HashMap<Key, Row>>>, /* Rows read by the last load, reused by the save that follows it */
+}
+
+impl SqliteStorage {
+    /* Open database, migrating it to the current version and importing the JSON files on first run */
+    fn open(sources: Vec<Source>) -> io::Result<SqliteStorage> {
+        let connection = Connection::open(files::data_path()?.join(DATABASE_FILE)).map_err(sql_error)?;
+
+        let version: usize = connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(sql_error)?;
+
+        /* Turn foreign keys off while migrating, so tables can be rebuilt */
+        connection.execute_batch("PRAGMA foreign_keys = OFF;").map_err(sql_error)?;
+
+        for (from, migration) in DATABASE_MIGRATIONS.iter().enumerate().skip(version) {
+            connection.execute_batch(&format!(
+                "BEGIN; {migration} PRAGMA user_version = {}; COMMIT;", from + 1
+            )).map_err(sql_error)?;
+        }
+
+        connection.execute_batch("PRAGMA foreign_keys = ON;").map_err(sql_error)?;
+
+        let mut storage = SqliteStorage { connection, sources, loaded: RefCell::new(None) };
+
+        if version == 0 {
+            let json = JsonStorage { sources: storage.sources.clone() };
+            copy(&json, &mut storage)?;
+        }
+
+        Ok(storage)
+    }
+
+    /* Load each saved wallpaper's row by key */
+    fn load_rows(&self) -> rusqlite::Result<HashMap<Key, Row>> {
+        let mut tags: HashMap<Key, BTreeSet<String>> = HashMap::new();
+        let mut statement = self.connection.prepare("SELECT source, path, tag FROM tags")?;
+        for row in statement.query_map([], |row| Ok(((row.get(0)?, row.get(1)?), row.get(2)?)))? {
+            let (key, tag) = row?;
+            tags.entry(key).or_default().insert(tag);
+        }
+
+        let mut members: HashMap<Key, BTreeSet<String>> = HashMap::new();
+        let mut statement = self.connection.prepare("SELECT source, path, collection FROM collection_members")?;
+        for row in statement.query_map([], |row| Ok(((row.get(0)?, row.get(1)?), row.get(2)?)))? {
+            let (key, collection) = row?;
+            members.entry(key).or_default().insert(collection);
+        }
+
+        let mut statement = self.connection.prepare(
+            "SELECT source, path, filename, is_day, rating, hash, suggestion, suggested_tags FROM wallpapers")?;
+
+        let rows = statement.query_map([], |row| {
+            let key: Key = (row.get(0)?, row.get(1)?);
+
+            let suggested_tags = row.get::<_, Option<String>>(7)?
+                .and_then(|text| serde_json::from_str(&text).ok())
+                .unwrap_or_default();
+
+            Ok((key.clone(), Row {
+                filename: row.get(2)?,
+                is_day: row.get(3)?,
+                rating: row.get(4)?,
+                hash: row.get(5)?,
+                suggestion: row.get(6)?,
+                suggested_tags,
+                tags: tags.remove(&key).unwrap_or_default(),
+                collections: members.remove(&key).unwrap_or_default(),
+            }))
+        })?;
+
+        rows.collect()
+    }
+}
+
+impl Storage for SqliteStorage {
+    /* Rows that cannot be read are reported and skipped */
+    fn load_wallpapers(&self) -> io::Result<HashSet<Wallpaper>> {
+        let rows = self.load_rows().map_err(sql_error)?;
+
+        let wallpapers = rows.iter()
+            .filter_map(|(key, row)| row.to_wallpaper(key, &self.sources)
+                .inspect_err(|err| warnings::warn("saved_wallpaper_unreadable", format!("Could not read saved wallpaper {} ({err})", key.1)))
+                .ok())
+            .collect();
+
+        self.loaded.replace(Some(rows));
+        Ok(wallpapers)
+    }
+
+    /* Only write wallpapers that have changed, and delete those no longer saved (including absolute paths now saved relative to a source) */
+    fn save_wallpapers(&mut self, wallpapers: &HashSet<Wallpaper>) -> io::Result<()> {
+        let mut saved = match self.loaded.take() {
+            Some(rows) => rows,
+            None => self.load_rows().map_err(sql_error)?,
+        };
+
+        let transaction = self.connection.transaction().map_err(sql_error)?;
+
+        for wallpaper in wallpapers {
+            let portable = wallpaper.to_portable(&self.sources);
+            let key: Key = (
+                portable.source().unwrap_or_default().to_string(),
+                portable.as_ref().to_string_lossy().into_owned(),
+            );
+
+            let row = Row::new(&key, &portable);
+
+            /* Rows that cannot be read were never loaded, so are kept rather than overwritten or deleted */
+            match saved.remove(&key) {
+                Some(saved) if saved == row || saved.to_wallpaper(&key, &self.sources).is_err() => {},
+                _ => write_wallpaper(&transaction, &key, &row).map_err(sql_error)?,
+            }
+        }
+
+        let removed = saved.iter()
+            .filter(|(key, row)| row.to_wallpaper(key, &self.sources).is_ok())
+            .map(|(key, _)| key);
+
+        for (source, path) in removed {
+            transaction.execute("DELETE FROM wallpapers WHERE source = ?1 AND path = ?2", [source, path])
+                .map_err(sql_error)?;
+        }
+
+        transaction.commit().map_err(sql_error)
+    }
+
+    fn load_collections(&self) -> io::Result<Vec<String>> {
+        let mut statement = self.connection
+            .prepare("SELECT name FROM collections ORDER BY position")
+            .map_err(sql_error)?;
+
+        let names = statement.query_map([], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .map_err(sql_error)?;
+
+        Ok(names)
+    }
+
+    fn save_collections(&mut self, collections: &[String]) -> io::Result<()> {
+        let transaction = self.connection.transaction().map_err(sql_error)?;
+
+        transaction.execute("DELETE FROM collections", []).map_err(sql_error)?;
+
+        for (position, name) in collections.iter().enumerate() {
+            transaction.execute("INSERT INTO collections (name, position) VALUES (?1, ?2)", params![name, position])
+                .map_err(sql_error)?;
+        }
+
+        transaction.commit().map_err(sql_error)
+    }
+
+    /* Paths are resolved against sources (those of unconfigured sources are left relative) */
+    fn tagged(&self, tag: &WeatherTag) -> io::Result<Vec<PathBuf>> {
+        let mut statement = self.connection
+            .prepare("SELECT source, path FROM tags WHERE tag = ?1 ORDER BY source, path")
+            .map_err(sql_error)?;
+
+        let keys: Vec<Key> = statement.query_map([tag_name(tag)], |row| Ok((row.get(0)?, row.get(1)?)))
+            .and_then(|rows| rows.collect())
+            .map_err(sql_error)?;
+
+        Ok(keys.into_iter()
+            .map(|(source, path)| sources::to_absolute(&self.sources, &source, &path).unwrap_or(PathBuf::from(path)))
+            .collect())
+    }
+
+    fn record_selection(&mut self, wallpaper: &Wallpaper, weather: Option<&Weather>) -> io::Result<()> {
+        let weather = weather.map(serde_json::to_string).transpose()?;
+
+        self.connection.execute(
+            "INSERT INTO selections (path, selected_at, weather) VALUES (?1, ?2, ?3)",
+            params![wallpaper.as_ref().to_string_lossy(), Local::now().to_rfc3339(), weather],
+        ).map_err(sql_error)?;
+
+        Ok(())
+    }
+}
+
+/* Insert or replace wallpaper row (with its path as saved) along with its tags and collection membership */
+fn write_wallpaper(transaction: &rusqlite::Transaction, (source, path): &Key, row: &Row) -> rusqlite::Result<()> {
+    let suggested_tags = (!row.suggested_tags.is_empty())
+        .then(|| serde_json::to_string(&row.suggested_tags).ok())
+        .flatten();
+
+    transaction.execute(
+        "INSERT INTO wallpapers (source, path, filename, is_day, rating, hash, suggestion, suggested_tags)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ON CONFLICT(source, path) DO UPDATE SET
+                filename = excluded.filename,
+                is_day = excluded.is_day,
+                rating = excluded.rating,
+                hash = excluded.hash,
+                suggestion = excluded.suggestion,
+                suggested_tags = excluded.suggested_tags",
+        params![
+            source,
+            path,
+            row.filename,
+            row.is_day,
+            row.rating,
+            row.hash,
+            row.suggestion,
+            suggested_tags,
+        ],
+    )?;
+
+    transaction.execute("DELETE FROM tags WHERE source = ?1 AND path = ?2", [source, path])?;
+    for tag in &row.tags {
+        transaction.execute("INSERT INTO tags (source, path, tag) VALUES (?1, ?2, ?3)", [source, path, tag])?;
+    }
+
+    transaction.execute("DELETE FROM collection_members WHERE source = ?1 AND path = ?2", [source, path])?;
+    for collection in &row.collections {
+        transaction.execute("INSERT INTO collection_members (source, path, collection) VALUES (?1, ?2, ?3)", [source, path, collection])?;
+    }
+
+    Ok(())
+}
+
+/* Name tag is saved under (as in JSON files) */
+fn tag_name(tag: &WeatherTag) -> String {
+    format!("{tag:?}")
+}
+
+fn parse_tags(names: &BTreeSet<String>) -> Result<HashSet<WeatherTag>, String> {
+    names.iter()
+        .map(|name| WeatherTag::iter()
+            .find(|tag| tag_name(tag) == *name)
+            .ok_or_else(|| format!("unknown tag {name}")))
+        .collect()
+}
+
+fn sql_error(err: rusqlite::Error) -> io::Error {
+    io::Error::other(err)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources() -> Vec<Source> {
+        vec![Source::new(PathBuf::from("/pictures"))]
+    }
+
+    #[test]
+    fn row_round_trips() {
+        let mut weather = Weather::default();
+        weather.set_tags(HashSet::from([WeatherTag::Rain, WeatherTag::Fog]));
+        weather.set_is_day(Some(false));
+
+        let mut wallpaper = Wallpaper::saved(PathBuf::from("/pictures/rain/night.png"), None, weather, Rating::new(4), Some(String::from("abc")));
+        wallpaper.set_collections(BTreeSet::from([String::from("Work")]));
+        wallpaper.set_suggested_tags(HashSet::from([WeatherTag::Storm]));
+
+        let portable = wallpaper.to_portable(&sources());
+        let key: Key = (String::from("pictures"), String::from("rain/night.png"));
+        assert_eq!((portable.source(), portable.as_ref()), (Some("pictures"), Path::new("rain/night.png")));
+
+        let loaded = Row::new(&key, &portable).to_wallpaper(&key, &sources()).unwrap();
+
+        assert_eq!(loaded.as_ref(), wallpaper.as_ref());
+        assert_eq!(loaded.weather, wallpaper.weather);
+        assert_eq!(loaded.rating(), wallpaper.rating());
+        assert_eq!(loaded.hash(), wallpaper.hash());
+        assert_eq!(loaded.collections(), wallpaper.collections());
+        assert_eq!(loaded.suggested_tags(), wallpaper.suggested_tags());
+        assert!(Row::new(&key, &loaded.to_portable(&sources())) == Row::new(&key, &portable));
+    }
+
+    #[test]
+    fn unknown_tag_is_unreadable() {
+        let wallpaper = Wallpaper::saved(PathBuf::from("sun.png"), None, Weather::default(), Rating::default(), None);
+        let key: Key = (String::new(), String::from("sun.png"));
+
+        let mut row = Row::new(&key, &wallpaper);
+        row.tags.insert(String::from("Hail"));
+
+        assert!(row.to_wallpaper(&key, &sources()).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use std::{collections::HashSet, fs, io, path::{Path, PathBuf}, time::UNIX_EPOCH};
+
+use crate::{files, wallpaper::Wallpaper};
+
+const THUMBNAILS_DIR: &str = "thumbnails";
+
+/* Maximum width and height of thumbnails (enough for high resolution terminal graphics protocols) */
+const THUMBNAIL_SIZE: u32 = 512;
+
+
+/* Get path of image's thumbnail, generating it if not already cached */
+pub fn thumbnail_path(file_path: &Path, hash: Option<&str>) -> io::Result<PathBuf> {
+    let thumbnail = thumbnails_path()?.join(thumbnail_name(file_path, hash)?);
+
+    if !thumbnail.exists() {
+        image::open(file_path)
+            .map(|image| image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE))
+            .and_then(|image| image.save_with_format(&thumbnail, image::ImageFormat::Png))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    }
+
+    Ok(thumbnail)
+}
+
+/* Delete thumbnails not matching the current contents of any wallpaper, returning how many files and bytes were removed */
+pub fn prune_thumbnails(wallpapers: &HashSet<Wallpaper>) -> io::Result<(usize, u64)> {
+    let current: HashSet<String> = wallpapers.iter()
+        .filter_map(|wallpaper| thumbnail_name(wallpaper.as_ref(), wallpaper.hash()).ok())
+        .collect();
+
+    let mut removed = 0;
+    let mut freed = 0;
+
+    for entry in fs::read_dir(thumbnails_path()?)?.filter_map(Result::ok) {
+        let is_current = entry.file_name().to_str().is_some_and(|name| current.contains(name));
+
+        if !is_current {
+            freed += entry.metadata().map_or(0, |metadata| metadata.len());
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+
+    Ok((removed, freed))
+}
+
+/* Total size of cached thumbnails in bytes */
+pub fn cache_size() -> io::Result<u64> {
+    Ok(fs::read_dir(thumbnails_path()?)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum())
+}
+
+/* Name thumbnails by content hash and modification time, so edited files get new thumbnails */
+fn thumbnail_name(file_path: &Path, hash: Option<&str>) -> io::Result<String> {
+    let hash = match hash {
+        Some(hash) => hash.to_string(),
+        None => files::hash_file(file_path)?,
+    };
+
+    let modified = fs::metadata(file_path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+
+    Ok(format!("{hash}-{modified}.png"))
+}
+
+/* Get thumbnail cache directory (nested in data directory) */
+fn thumbnails_path() -> io::Result<PathBuf> {
+    let thumbnails_dir = files::data_path()?.join(THUMBNAILS_DIR);
+    fs::create_dir_all(&thumbnails_dir)?;
+
+    Ok(thumbnails_dir)
+}
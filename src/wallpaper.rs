@@ -1,41 +1,58 @@
-use std::{cmp, collections::HashSet, fmt::{self, Display}, fs, hash::{Hash, Hasher}, io, path::{Path, PathBuf}};
+use std::{cmp, collections::{BTreeSet, HashSet}, fmt::{self, Display}, hash::{Hash, Hasher}, io, path::{Path, PathBuf}};
 
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::{Error, files, weather::Weather};
+use crate::{Error, analysis::{self, Detection, Suggestion}, backups, files, formats, metadata, rating::{self, Rating}, schema::{self, Migration}, settings::Config, sources::{self, Source}, thumbnails, weather::{Weather, WeatherTag}};
 
 const PREVIEW_WIDTH: u32 = 64;
 const PREVIEW_OFFSET: u16 = 8;
 
-const FAV_WEIGHT_MULT: usize = 2;
-
 const WALLPAPER_TAGS_FILE: &str = "wallpaper_tags.json";
 
+/* Migrations from each version of the saved tags file to the next */
+const MIGRATIONS: [Migration; 2] = [
+    favourited_to_rating, /* 0 -> 1 */
+    |wallpapers| wallpapers, /* 1 -> 2: absolute paths still load, and are saved relative to their source */
+];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Wallpaper {
     filename: String,
-    path: PathBuf,
+    path: PathBuf, /* Absolute once loaded, saved relative to source if in one */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>, /* Name of source path is relative to (unresolved if not configured on this machine) */
     pub weather: Weather,
-    favourited: bool
+    #[serde(default)]
+    rating: Rating,
+    #[serde(default)]
+    collections: BTreeSet<String>,
+    #[serde(default)]
+    hash: Option<String>, /* Hash of file contents, used to re-link renamed or moved files */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    suggestion: Option<Suggestion>, /* Day/night suggested by image analysis, awaiting review */
+    #[serde(default, skip_serializing_if = "HashSet::is_empty", serialize_with = "schema::sorted")]
+    suggested_tags: HashSet<WeatherTag>, /* Tags suggested by image analysis, confirmed in tag editor */
 }
 
 impl Eq for Wallpaper {}
 impl PartialEq for Wallpaper {
     fn eq(&self, other: &Self) -> bool {
-        self.path == other.path
+        self.path == other.path && self.source == other.source
     }
 }
 
 impl Hash for Wallpaper {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.path.hash(state)
+        self.path.hash(state);
+        self.source.hash(state);
     }
 }
 
 impl PartialOrd for Wallpaper {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        self.filename.partial_cmp(&other.filename)
+        Some(self.cmp(other))
     }
 }
 
@@ -54,51 +71,214 @@ impl AsRef<Path> for Wallpaper {
 /* Print name, path and tags of Wallpaper */
 impl Display for Wallpaper {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}{} ({})\n Weather depicted: {}", 
-            self.favourited.then_some("\u{2605} ").unwrap_or(""),
+        write!(f, "{} ({})\n Rating: {}\n Weather depicted: {}", 
             self.filename.bold(), 
             self.path.display().to_string().dimmed(),
+            self.rating,
             self.weather
-        )
+        )?;
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n Suggested: {}", suggestion)?;
+        }
+
+        if !self.suggested_tags.is_empty() {
+            write!(f, "\n Suggested tags: {}", self.suggested_tags.iter()
+                .map(WeatherTag::to_string)
+                .collect::<Vec<String>>()
+                .join(", "))?;
+        }
+
+        if !self.collections.is_empty() {
+            write!(f, "\n Collections: {}", 
+                self.collections.iter().cloned().collect::<Vec<String>>().join(", "))?;
+        }
+
+        Ok(())
     }
 }
 
 impl Wallpaper {
     
-    /* Load wallpaper from directory */
-    pub fn new(file: fs::DirEntry) -> Wallpaper {
-        let filename = file.file_name().into_string().unwrap();
+    /* Load wallpaper from file, tagging it from keywords (e.g. folder names), its metadata and image analysis */
+    pub fn new(path: PathBuf, hash: Option<String>, keywords: &[String], config: &Config) -> Wallpaper {
+        let mut wallpaper = Wallpaper {
+            filename: filename(&path),
+            path,
+            source: None,
+            weather: Weather::default(),
+            rating: Rating::default(),
+            collections: BTreeSet::new(),
+            hash,
+            suggestion: None,
+            suggested_tags: HashSet::new(),
+        };
+
+        let mut day_night_set = false;
+
+        /* Import day/night from capture time and location, and tags from filename and embedded keywords */
+        if config.import_metadata() {
+            let imported = metadata::import(&wallpaper.path);
+
+            if imported.is_day.is_some() {
+                wallpaper.weather.set_is_day(imported.is_day);
+                day_night_set = true;
+            }
 
+            day_night_set |= wallpaper.weather.add_keywords(&imported.keywords);
+        }
+
+        /* Given keywords take precedence over metadata */
+        day_night_set |= wallpaper.weather.add_keywords(keywords);
+
+        /* Analyse image for day/night (if not already known) and weather tags (if none found) */
+        let detect_day_night = !day_night_set && config.detection() != Detection::Off;
+        let detect_tags = config.suggest_tags() && wallpaper.weather.tags().is_empty();
+
+        if detect_day_night || detect_tags {
+            if let Some(analysis) = analysis::analyse(&wallpaper.path) {
+                if detect_day_night {
+                    wallpaper.suggest_day_night(analysis.day_night, config);
+                }
+
+                if detect_tags {
+                    wallpaper.set_suggested_tags(analysis.tags);
+                }
+            }
+        }
+
+        wallpaper
+    }
+
+    /* Wallpaper as saved (path relative to the named source if given), before its collections and suggestions are set */
+    pub fn saved(path: PathBuf, source: Option<String>, weather: Weather, rating: Rating, hash: Option<String>) -> Wallpaper {
         Wallpaper {
-            filename: filename.clone(),
-            path: file.path(),
-            weather: Weather::default(),
-            favourited: false,
+            filename: filename(&path),
+            path,
+            source,
+            weather,
+            rating,
+            collections: BTreeSet::new(),
+            hash,
+            suggestion: None,
+            suggested_tags: HashSet::new(),
         }
     }
 
-    pub fn is_favourited(&self) -> bool {
-        self.favourited
+    pub fn hash(&self) -> Option<&str> {
+        self.hash.as_deref()
     }
 
-    pub fn toggle_favourited(&mut self) {
-        self.favourited = !self.favourited;
+    /* Hash file contents if not already hashed */
+    pub fn ensure_hash(&mut self) {
+        if self.hash.is_none() {
+            self.hash = files::hash_file(&self.path).ok();
+        }
     }
 
-    pub fn is_valid(&self) -> bool {
-        self.path.exists()
+    /* Point wallpaper at the new location of its (renamed or moved) file */
+    pub fn relink(&mut self, path: PathBuf) {
+        self.filename = filename(&path);
+        self.path = path;
+        self.source = None;
     }
 
-    pub fn get_weight(&self, weather: &Weather) -> usize {
-        let matching_tags = self.weather.tags().intersection(weather.tags()).count();
+    /* Whether path is absolute (false if saved relative to a source not configured on this machine) */
+    pub fn is_resolved(&self) -> bool {
+        self.source.is_none()
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    /* Copy with path relative to the source containing it, for saving */
+    pub fn to_portable(&self, sources: &[Source]) -> Wallpaper {
+        let mut portable = self.clone();
+
+        if let Some((name, relative)) = self.is_resolved().then(|| sources::to_relative(sources, &self.path)).flatten() {
+            portable.path = PathBuf::from(relative);
+            portable.source = Some(name);
+        }
+
+        portable
+    }
+
+    /* Make path absolute again if saved relative to a configured source */
+    pub fn resolve(&mut self, sources: &[Source]) {
+        let resolved = self.source.as_ref()
+            .and_then(|name| sources::to_absolute(sources, name, &self.path.to_string_lossy()));
+
+        if let Some(path) = resolved {
+            self.path = path;
+            self.source = None;
+        }
+    }
+
+    pub fn suggestion(&self) -> Option<&Suggestion> {
+        self.suggestion.as_ref()
+    }
+
+    pub fn set_suggestion(&mut self, suggestion: Option<Suggestion>) {
+        self.suggestion = suggestion;
+    }
+
+    pub fn clear_suggestion(&mut self) {
+        self.suggestion = None;
+    }
+
+    pub fn suggested_tags(&self) -> &HashSet<WeatherTag> {
+        &self.suggested_tags
+    }
 
-        if self.favourited {
-            matching_tags * FAV_WEIGHT_MULT
+    pub fn set_suggested_tags(&mut self, tags: HashSet<WeatherTag>) {
+        self.suggested_tags = tags;
+    }
+
+    /* Apply suggested day/night if confident enough (and enabled), otherwise save it for review */
+    pub fn suggest_day_night(&mut self, suggestion: Suggestion, config: &Config) {
+        if config.detection() == Detection::Apply && suggestion.confidence() >= config.detection_confidence() {
+            self.weather.set_is_day(Some(suggestion.is_day()));
         } else {
-            matching_tags
+            self.suggestion = Some(suggestion);
         }
     }
 
+    pub fn rating(&self) -> Rating {
+        self.rating
+    }
+
+    pub fn set_rating(&mut self, rating: Rating) {
+        self.rating = rating;
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.is_resolved() && self.path.exists()
+    }
+
+    pub fn collections(&self) -> &BTreeSet<String> {
+        &self.collections
+    }
+
+    pub fn set_collections(&mut self, collections: BTreeSet<String>) {
+        self.collections = collections;
+    }
+
+    pub fn in_collection(&self, collection: &str) -> bool {
+        self.collections.contains(collection)
+    }
+
+    /* Whether the wallpaper can be chosen at all (non-zero rating multiplier) */
+    pub fn is_enabled(&self, config: &Config) -> bool {
+        config.rating_weight(self.rating) > 0
+    }
+
+    pub fn get_weight(&self, weather: &Weather, config: &Config) -> usize {
+        let matching_tags = self.weather.tags().intersection(weather.tags()).count();
+
+        matching_tags * config.rating_weight(self.rating)
+    }
+
     /* Print info and image to console */
     pub fn print(&self) {
         println!("{self}");
@@ -109,7 +289,7 @@ impl Wallpaper {
         println!();
     }
 
-    /* Output preview of photo in terminal */
+    /* Output preview of photo in terminal (from its cached thumbnail if possible) */
     fn render_preview(&self) -> Result<(u32, u32), Error> {
         let conf = viuer::Config {
             absolute_offset: false,
@@ -125,34 +305,172 @@ impl Wallpaper {
             ..Default::default()
         };
         
-        viuer::print_from_file(self.path.to_str().unwrap(), &conf)
+        let path = thumbnails::thumbnail_path(&self.path, self.hash())
+            .unwrap_or_else(|_| self.path.clone());
+
+        viuer::print_from_file(path.to_str().unwrap(), &conf)
             .map_err(|_| Error::ImagePrintFail)
     }
 
     pub fn set(&self) -> Result<(), Error> {
-        let path_str = self.path.to_str().unwrap();
+        let path = formats::settable_path(&self.path, self.hash())?;
 
-        wallpaper_setting::set_from_path(path_str)
+        wallpaper_setting::set_from_path(path.to_str().unwrap())
             .map_err(|_| Error::InvalidWallpaper)
     }
 
 }
 
 
-/* Save map of tags associated with each file */
-pub fn save_wallpapers(wallpapers: &HashSet<Wallpaper>) -> io::Result<()> {
-    fs::write(wallpaper_tags_path()?, serde_json::to_string_pretty(&wallpapers)?)
+/* Get name of file from its path */
+pub fn filename(path: &Path) -> String {
+    path.file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().into_owned())
 }
 
-/* Load map of tags associated with each file */
-pub fn load_wallpapers() -> io::Result<HashSet<Wallpaper>> {
-    let contents = fs::read_to_string(wallpaper_tags_path()?)?;
-    let parsed: Vec<Wallpaper> = serde_json::from_str(&contents)?;
+/* Save map of tags associated with each file (relative to sources), keeping a backup of each change */
+pub fn save_wallpapers(wallpapers: &HashSet<Wallpaper>, sources: &[Source]) -> io::Result<()> {
+    let path = wallpaper_tags_path()?;
+
+    let mut portable: Vec<Wallpaper> = wallpapers.iter()
+        .map(|wallpaper| wallpaper.to_portable(sources))
+        .collect();
+    portable.sort_by(|a, b| (&a.source, &a.path).cmp(&(&b.source, &b.path)));
+
+    if schema::save(&path, &portable, &MIGRATIONS)? {
+        backups::back_up(&path)?;
+    }
+
+    Ok(())
+}
+
+/* Load map of tags associated with each file, resolving paths relative to sources */
+pub fn load_wallpapers(sources: &[Source]) -> io::Result<HashSet<Wallpaper>> {
+    let parsed: Vec<Wallpaper> = schema::load(&wallpaper_tags_path()?, &MIGRATIONS)?;
+
+    Ok(parsed.into_iter()
+        .map(|mut wallpaper| {
+            wallpaper.resolve(sources);
+            wallpaper
+        })
+        .collect::<HashSet<Wallpaper>>())
+}
+
+/* Replace the favourited flag with a star rating (favourites get full stars) */
+fn favourited_to_rating(mut wallpapers: Value) -> Value {
+    for wallpaper in wallpapers.as_array_mut().into_iter().flatten().filter_map(Value::as_object_mut) {
+        if let Some(favourited) = wallpaper.remove("favourited") {
+            let stars = if favourited.as_bool() == Some(true) { rating::MAX_RATING } else { Rating::default().stars() };
+            wallpaper.entry("rating").or_insert(Value::from(stars));
+        }
+    }
 
-    Ok(parsed.into_iter().collect::<HashSet<Wallpaper>>())
+    wallpapers
 }
 
 /* Helper function to get path to file of saved tags */
-fn wallpaper_tags_path() -> io::Result<PathBuf> {
-    files::data_path().and_then(|path| Ok(path.join(WALLPAPER_TAGS_FILE)))
+pub fn wallpaper_tags_path() -> io::Result<PathBuf> {
+    files::data_path().map(|path| path.join(WALLPAPER_TAGS_FILE))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const PICTURES: &str = "/home/user/Pictures/weather_wallpapers";
+
+    fn sources() -> Vec<Source> {
+        vec![Source::new(PathBuf::from(PICTURES)), Source::new(PathBuf::from("/mnt/share/art"))]
+    }
+
+    /* Load saved tags file contents */
+    fn load(saved: Value) -> Vec<Wallpaper> {
+        schema::parse(&saved.to_string(), &MIGRATIONS).unwrap()
+    }
+
+    #[test]
+    fn unversioned_favourites_become_ratings() {
+        let wallpapers = load(json!([
+            {
+                "filename": "rain.png",
+                "path": format!("{PICTURES}/rain.png"),
+                "weather": { "tags": ["Rain", "Cloud"], "is_day": false },
+                "favourited": true,
+            },
+            {
+                "filename": "sun.png",
+                "path": format!("{PICTURES}/sun.png"),
+                "weather": { "tags": ["Sun"], "is_day": true },
+                "favourited": false,
+            },
+        ]));
+
+        assert_eq!(wallpapers[0].rating(), Rating::new(rating::MAX_RATING));
+        assert_eq!(wallpapers[0].weather.tags(), &HashSet::from([WeatherTag::Rain, WeatherTag::Cloud]));
+        assert_eq!(wallpapers[0].weather.is_day(), Some(false));
+        assert_eq!(wallpapers[1].rating(), Rating::default());
+        assert_eq!(wallpapers[1].as_ref(), Path::new(&format!("{PICTURES}/sun.png")));
+    }
+
+    #[test]
+    fn version_1_keeps_ratings_and_absolute_paths() {
+        let wallpapers = load(json!({
+            "version": 1,
+            "data": [{
+                "filename": "fog.png",
+                "path": "/elsewhere/fog.png",
+                "weather": { "tags": ["Fog"], "is_day": null },
+                "rating": 2,
+            }],
+        }));
+
+        assert_eq!(wallpapers[0].rating(), Rating::new(2));
+        assert_eq!(wallpapers[0].as_ref(), Path::new("/elsewhere/fog.png"));
+        assert!(wallpapers[0].is_resolved());
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let contents = json!({ "version": MIGRATIONS.len() + 1, "data": [] }).to_string();
+        assert!(schema::parse::<Vec<Wallpaper>>(&contents, &MIGRATIONS).is_err());
+    }
+
+    #[test]
+    fn paths_round_trip_through_sources() {
+        let original = load(json!([{
+            "filename": "night.png",
+            "path": "/mnt/share/art/storms/night.png",
+            "weather": { "tags": ["Storm"], "is_day": false },
+        }])).remove(0);
+
+        let portable = original.to_portable(&sources());
+        assert_eq!(portable.source(), Some("art"));
+        assert_eq!(portable.as_ref(), Path::new("storms/night.png"));
+
+        let mut loaded = load(json!({ "version": MIGRATIONS.len(), "data": [portable] })).remove(0);
+        loaded.resolve(&sources());
+
+        assert_eq!(loaded.as_ref(), original.as_ref());
+        assert!(loaded.is_resolved());
+    }
+
+    #[test]
+    fn unconfigured_source_stays_relative() {
+        let mut wallpaper = load(json!([{
+            "filename": "night.png",
+            "path": "storms/night.png",
+            "source": "laptop",
+            "weather": { "tags": [], "is_day": null },
+        }])).remove(0);
+
+        wallpaper.resolve(&sources());
+        assert!(!wallpaper.is_resolved());
+
+        let portable = wallpaper.to_portable(&sources());
+        assert_eq!(portable.source(), Some("laptop"));
+        assert_eq!(portable.as_ref(), Path::new("storms/night.png"));
+    }
 }
@@ -1,15 +1,59 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, path::Path};
 
 use console::Term;
 use dialoguer::{Input, MultiSelect, Select};
 use strum::IntoEnumIterator;
 
-use crate::{Error, files, format_items, wallpaper::{self, Wallpaper}, weather::WeatherTag};
+use crate::{Error, backups, bundle::{self, ImportMode}, collections, files, format_items, rating::Rating, settings::Config, sidecar, storage::{self, Backend}, wallpaper::{self, Wallpaper}, weather::WeatherTag};
 
 
+/* Choose between editing, reviewing, browsing, importing and exporting tags */
+pub fn tags_menu(config: &Config) {
+    let suggestions = files::load_all_wallpapers(config).unwrap().iter()
+        .filter(|wallpaper| wallpaper.suggestion().is_some())
+        .count();
+
+    /* Backups are only kept of the JSON file */
+    let has_backups = config.storage() == Backend::Json;
+
+    let mut items = vec![
+        String::from("Edit tags"),
+        format!("Review day/night suggestions [{suggestions}]"),
+        String::from("Browse by tag"),
+        String::from("Export sidecar files"),
+        String::from("Export tags (CSV or JSON bundle)"),
+        String::from("Import tags (CSV or JSON bundle)"),
+    ];
+
+    if has_backups {
+        items.push(String::from("Restore tags from backup"));
+    }
+
+    items.push(String::from("Back"));
+
+    let choice = Select::new()
+        .with_prompt("Tags")
+        .items(&format_items(items))
+        .default(0)
+        .report(false)
+        .interact_opt()
+        .unwrap();
+
+    match choice {
+        Some(0) => edit_wallpaper_tags(config),
+        Some(1) => review_suggestions(config),
+        Some(2) => browse_by_tag(config),
+        Some(3) => export_sidecars(config),
+        Some(4) => export_tags(config),
+        Some(5) => import_tags(config),
+        Some(6) if has_backups => restore_backup(),
+        _ => (), /* Back */
+    }
+}
+
 /* Edit the tags of all wallpapers */
-pub fn edit_wallpaper_tags() {
-    let mut wallpapers = files::load_all_wallpapers()
+fn edit_wallpaper_tags(config: &Config) {
+    let mut wallpapers = files::load_all_wallpapers(config).unwrap()
         .into_iter()
         .collect::<Vec<Wallpaper>>();
 
@@ -17,15 +61,273 @@ pub fn edit_wallpaper_tags() {
     
     let current_wallpaper = wallpaper_setting::get().unwrap();
 
-    edit_menu(0, &mut wallpapers);
+    edit_menu(0, &mut wallpapers, config);
+
+    wallpaper_setting::set_from_path(&current_wallpaper).unwrap();
+
+    files::save_all_wallpapers(&wallpapers.into_iter().collect(), config).unwrap();
+}
+
+/* Edit the tags of a single wallpaper (e.g. the one currently set), returning the edited wallpaper unless cancelled */
+pub fn edit_wallpaper(wallpaper: &Wallpaper, config: &Config) -> Option<Wallpaper> {
+    let mut wallpapers = files::load_all_wallpapers(config).unwrap();
+    let mut edited = wallpapers.get(wallpaper).cloned()?;
+
+    Term::stdout().clear_screen().unwrap();
+
+    match edited.edit_tags() {
+        Ok(()) => {},
+        Err(Error::Interrupted) => return None,
+        error => error.unwrap(),
+    }
+
+    wallpapers.replace(edited.clone());
+    files::save_all_wallpapers(&wallpapers, config).unwrap();
+
+    Some(edited)
+}
+
+/* Accept or reject day/night suggested by image analysis */
+fn review_suggestions(config: &Config) {
+    let mut wallpapers = files::load_all_wallpapers(config).unwrap()
+        .into_iter()
+        .collect::<Vec<Wallpaper>>();
+
+    wallpapers.sort();
+
+    let to_review: Vec<usize> = (0..wallpapers.len())
+        .filter(|&i| wallpapers[i].suggestion().is_some())
+        .collect();
+
+    let current_wallpaper = wallpaper_setting::get().unwrap();
+
+    for (n, &i) in to_review.iter().enumerate() {
+        let wallpaper = &mut wallpapers[i];
+        let suggestion = wallpaper.suggestion().cloned().unwrap();
+
+        Term::stdout().clear_screen().unwrap();
+        wallpaper.set().unwrap();
+
+        print!("[{}/{}] ", n + 1, to_review.len());
+        wallpaper.print();
+
+        let choice = Select::new()
+            .with_prompt(format!("Suggested: {suggestion}"))
+            .items(&format_items(vec![
+                "Accept suggestion",
+                "Day",
+                "Night",
+                "Both",
+                "Skip",
+                "Stop reviewing",
+            ]))
+            .default(0)
+            .report(false)
+            .interact_opt()
+            .unwrap();
+
+        let is_day = match choice {
+            Some(0) => Some(suggestion.is_day()),
+            Some(1) => Some(true),
+            Some(2) => Some(false),
+            Some(3) => None,
+            Some(4) => continue, /* Skip */
+            _ => break, /* Stop */
+        };
+
+        wallpaper.weather.set_is_day(is_day);
+        wallpaper.clear_suggestion();
+    }
+
+    Term::stdout().clear_screen().unwrap();
 
     wallpaper_setting::set_from_path(&current_wallpaper).unwrap();
 
-    wallpaper::save_wallpapers(&wallpapers.into_iter().collect()).unwrap();
+    files::save_all_wallpapers(&wallpapers.into_iter().collect(), config).unwrap();
+}
+
+/* List wallpapers with a chosen tag */
+fn browse_by_tag(config: &Config) {
+    let tags: Vec<WeatherTag> = WeatherTag::iter().collect();
+
+    let Some(index) = Select::new()
+        .with_prompt("Select tag")
+        .items(&format_items(tags.clone()))
+        .default(0)
+        .report(false)
+        .interact_opt()
+        .unwrap() else {
+        return;
+    };
+
+    let tagged = storage::open(config)
+        .and_then(|store| store.tagged(&tags[index]))
+        .unwrap_or_default();
+
+    let term = Term::stdout();
+    term.clear_screen().unwrap();
+
+    println!("Wallpapers tagged {} [{}]:", tags[index], tagged.len());
+
+    for path in tagged {
+        println!("  {}", path.display());
+    }
+
+    println!("\nPress enter to return");
+    term.read_line().unwrap();
+    term.clear_screen().unwrap();
+}
+
+/* Write a sidecar file next to every wallpaper, so its tags travel with the image */
+fn export_sidecars(config: &Config) {
+    let wallpapers = files::load_all_wallpapers(config).unwrap();
+
+    let term = Term::stdout();
+    term.clear_screen().unwrap();
+
+    match sidecar::export_sidecars(&wallpapers) {
+        Ok(written) => println!("Wrote {written} sidecar files"),
+        Err(err) => println!("Could not write sidecar files: {err}"),
+    }
+
+    println!("\nPress enter to return");
+    term.read_line().unwrap();
+    term.clear_screen().unwrap();
+}
+
+/* Export tags of all wallpapers to share with other machines or users */
+fn export_tags(config: &Config) {
+    let Some(format) = Select::new()
+        .with_prompt("Export format")
+        .items(&format_items(vec!["CSV", "JSON bundle (includes collection names)"]))
+        .default(0)
+        .report(false)
+        .interact_opt()
+        .unwrap() else {
+        return;
+    };
+
+    let default_path = files::wallpapers_path().unwrap()
+        .join(if format == 0 { "wallpaper_tags.csv" } else { "wallpaper_tags.bundle.json" });
+
+    let path = Input::<String>::new()
+        .with_prompt("Export to")
+        .with_initial_text(default_path.display().to_string())
+        .interact_text()
+        .unwrap();
+
+    let term = Term::stdout();
+    term.clear_screen().unwrap();
+
+    match bundle::export(config, Path::new(&path)) {
+        Ok(count) => println!("Exported tags of {count} wallpapers to {path}"),
+        Err(err) => println!("Could not export tags: {err}"),
+    }
+
+    println!("\nPress enter to return");
+    term.read_line().unwrap();
+    term.clear_screen().unwrap();
+}
+
+/* Import tags exported from another library, matching wallpapers by contents */
+fn import_tags(config: &Config) {
+    let path = Input::<String>::new()
+        .with_prompt("Import from (.csv or .json)")
+        .validate_with(|input: &String| Path::new(input).is_file().then_some(()).ok_or("File does not exist"))
+        .interact_text()
+        .unwrap();
+
+    let modes: Vec<ImportMode> = ImportMode::iter().collect();
+
+    let Some(mode) = Select::new()
+        .with_prompt("Import mode")
+        .items(&format_items(vec![
+            "Merge (add tags and collections, keep day/night and ratings of tagged wallpapers)",
+            "Overwrite (replace tags, day/night, ratings and collections)",
+        ]))
+        .default(0)
+        .report(false)
+        .interact_opt()
+        .unwrap() else {
+        return;
+    };
+
+    let term = Term::stdout();
+    term.clear_screen().unwrap();
+
+    match bundle::import(config, Path::new(&path), modes[mode]) {
+        Ok(summary) => println!("{summary}"),
+        Err(err) => println!("Could not import tags: {err}"),
+    }
+
+    println!("\nPress enter to return");
+    term.read_line().unwrap();
+    term.clear_screen().unwrap();
+}
+
+/* Replace saved tags with one of the automatic backups */
+fn restore_backup() {
+    let tags_path = wallpaper::wallpaper_tags_path().unwrap();
+    let backups = backups::list_backups(&tags_path).unwrap_or_default();
+
+    let term = Term::stdout();
+    term.clear_screen().unwrap();
+
+    if backups.is_empty() {
+        println!("No backups found");
+    } else {
+        let choice = Select::new()
+            .with_prompt("Restore tags saved at")
+            .items(&format_items(backups.iter()
+                .map(|backup| backup.created().format("%Y-%m-%d %H:%M:%S").to_string())
+                .collect()))
+            .default(0)
+            .report(false)
+            .interact_opt()
+            .unwrap();
+
+        let Some(index) = choice else {
+            return;
+        };
+
+        match backups::restore(&backups[index], &tags_path) {
+            Ok(_) => println!("Restored tags from {}", backups[index].created().format("%Y-%m-%d %H:%M:%S")),
+            Err(err) => println!("Could not restore tags: {err}"),
+        }
+    }
+
+    println!("\nPress enter to return");
+    term.read_line().unwrap();
+    term.clear_screen().unwrap();
+}
+
+/* List wallpapers with identical file contents */
+pub fn show_duplicates(config: &Config) {
+    let wallpapers = files::load_all_wallpapers(config).unwrap();
+    let duplicates = files::find_duplicates(&wallpapers);
+
+    let term = Term::stdout();
+    term.clear_screen().unwrap();
+
+    if duplicates.is_empty() {
+        println!("No duplicate wallpapers found");
+    }
+
+    for (i, group) in duplicates.iter().enumerate() {
+        println!("Duplicate set {}:", i + 1);
+
+        for wallpaper in group {
+            println!("  {}", wallpaper.as_ref().display());
+        }
+    }
+
+    println!("\nPress enter to return");
+    term.read_line().unwrap();
+    term.clear_screen().unwrap();
 }
 
 /* Edit the tags of a wallpaper */
-fn edit_menu(index: usize, wallpapers: &mut Vec<Wallpaper>) {
+fn edit_menu(index: usize, wallpapers: &mut Vec<Wallpaper>, config: &Config) {
     Term::stdout().clear_screen().unwrap();
     
     if index >= wallpapers.len() {
@@ -37,21 +339,21 @@ fn edit_menu(index: usize, wallpapers: &mut Vec<Wallpaper>) {
     print!("[{}/{}] ", index + 1, wallpapers.len() + 1);
 
     match wallpapers[index].edit_tags() {
-        Ok(_) => edit_menu(index + 1, wallpapers),
-        Err(Error::Interrupted) => interrupted_menu(index, wallpapers),
+        Ok(_) => edit_menu(index + 1, wallpapers, config),
+        Err(Error::Interrupted) => interrupted_menu(index, wallpapers, config),
         error => error.unwrap(), 
     }
 }
 
 /* Interrupted editing of tags (skip/goto/quit) */
-fn interrupted_menu(index: usize, wallpapers: &mut Vec<Wallpaper>) {
-    let fav_item: &str = wallpapers[index].is_favourited()
-        .then_some("Unfavourite").unwrap_or("Set as favourite");
+fn interrupted_menu(index: usize, wallpapers: &mut Vec<Wallpaper>, config: &Config) {
+    let rating_item = format!("Set rating [{}]", wallpapers[index].rating());
     
     let control = Select::new()
         .with_prompt("Interrupted")
         .items(&format_items(vec![
-            fav_item,
+            rating_item.as_str(),
+            "Edit collections",
             "Next",
             "Prev",
             "Go to ",
@@ -64,31 +366,53 @@ fn interrupted_menu(index: usize, wallpapers: &mut Vec<Wallpaper>) {
         .unwrap();
 
     let new_index = match control {
-        0 => { /* Toggle whether */
-            wallpapers[index].toggle_favourited(); 
+        0 => { /* Set star rating */
+            let rating = rating_menu(wallpapers[index].rating());
+            wallpapers[index].set_rating(rating); 
+            index
+        },
+        1 => { /* Add to or remove from collections */
+            collections::edit_membership(&mut wallpapers[index], config).unwrap();
             index
         },
-        1 => index + 1, /* Next */
-        2 => index.checked_sub(1).unwrap_or(0), /* Prev */
-        3 => goto_menu(wallpapers), /* Goto x */ 
-        4 => { /* Clear all tags */
-            wallpaper::save_wallpapers(&HashSet::new()).unwrap();
+        2 => index + 1, /* Next */
+        3 => index.saturating_sub(1), /* Prev */
+        4 => goto_menu(wallpapers), /* Goto x */ 
+        5 => { /* Clear all tags */
+            storage::open(config)
+                .and_then(|mut store| store.save_wallpapers(&HashSet::new()))
+                .unwrap();
 
-            *wallpapers = files::load_all_wallpapers()
+            *wallpapers = files::load_all_wallpapers(config).unwrap()
                 .into_iter()
                 .collect::<Vec<Wallpaper>>();
             
             wallpapers.len()
         },
-        5 => wallpapers.len(), /* Quit */
+        6 => wallpapers.len(), /* Quit */
         _ => unreachable!(),
     };
 
-    edit_menu(new_index, wallpapers)
+    edit_menu(new_index, wallpapers, config)
+}
+
+/* Handle input for star rating */
+fn rating_menu(current: Rating) -> Rating {
+    let ratings: Vec<Rating> = Rating::all().collect();
+
+    let choice = Select::new()
+        .with_prompt("Set rating (0 stars disables the wallpaper)")
+        .items(&format_items(ratings.clone()))
+        .default(current.stars() as usize)
+        .report(false)
+        .interact_opt()
+        .unwrap();
+
+    choice.map_or(current, |index| ratings[index])
 }
 
 /* Handle input for goto */
-fn goto_menu(wallpapers: &mut Vec<Wallpaper>) -> usize {
+fn goto_menu(wallpapers: &[Wallpaper]) -> usize {
     let goto_index = Input::<usize>::new()
         .with_prompt("Enter index of wallpaper to edit")
         .validate_with(|input: &usize| 
@@ -110,14 +434,18 @@ impl Wallpaper {
     fn edit_tags(&mut self) -> Result<(), Error> {
         self.print();
         
+        /* Pre-check suggested tags if not yet tagged */
+        let suggesting = self.weather.tags().is_empty() && !self.suggested_tags().is_empty();
+        let checked = if suggesting { self.suggested_tags() } else { self.weather.tags() };
+
         /* Load tag options */
         let options: Vec<(String, bool)> = WeatherTag::iter()
-            .map(|tag| (tag.to_string(), self.weather.tags().contains(&tag)))
+            .map(|tag| (tag.to_string(), checked.contains(&tag)))
             .collect();
     
         /* Set what weather is depicted */
         let input = MultiSelect::new()
-            .with_prompt("Select weather tags")
+            .with_prompt(if suggesting { "Select weather tags (suggestions checked)" } else { "Select weather tags" })
             .items_checked(&options)
             .report(false)
             .interact_opt()
@@ -141,9 +469,11 @@ impl Wallpaper {
                 .filter_map(|(i, tag)| input.contains(&i).then_some(tag))
                 .collect::<HashSet<WeatherTag>>()
         );
+        self.set_suggested_tags(HashSet::new());
     
-        /* Update day/night */
+        /* Update day/night (replacing any suggestion) */
         self.weather.set_is_day([Some(true), Some(false), None][day_night]);
+        self.clear_suggestion();
     
         Ok(())
     }
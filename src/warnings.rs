@@ -0,0 +1,35 @@
+use std::{mem, sync::Mutex};
+
+/* Warnings collected for the daemon to log, or None to print them as they happen */
+static COLLECTED: Mutex<Option<Vec<Warning>>> = Mutex::new(None);
+
+
+/* Problem that does not stop an operation (e.g. an unreadable file moved aside), reported without a terminal prompt */
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub event: &'static str, /* Name the daemon logs it under */
+    pub message: String,
+}
+
+/* Print warning to stderr, or keep it for the daemon to log if it is collecting them */
+pub fn warn(event: &'static str, message: String) {
+    let mut collected = COLLECTED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    match collected.as_mut() {
+        Some(warnings) => warnings.push(Warning { event, message }),
+        None => eprintln!("{message}"),
+    }
+}
+
+/* Keep warnings from now on instead of printing them (stderr may be a log file, or nobody's terminal) */
+pub fn collect() {
+    COLLECTED.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get_or_insert_with(Vec::new);
+}
+
+/* Take the warnings collected since last taken */
+pub fn take() -> Vec<Warning> {
+    COLLECTED.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_mut()
+        .map(mem::take)
+        .unwrap_or_default()
+}
@@ -1,14 +1,17 @@
-use std::{collections::{HashMap, HashSet}, fmt::{self, Display}};
+use std::{collections::{HashMap, HashSet}, fmt::{self, Display}, sync::LazyLock};
 
+use clap::ValueEnum;
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 use crate::weather_api::{self, WeatherData};
-use crate::Error;
+use crate::{Error, schema};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Weather {
+    #[serde(serialize_with = "schema::sorted")]
     tags: HashSet<WeatherTag>, 
     is_day: Option<bool>,
 }
@@ -29,6 +32,26 @@ impl Weather {
     pub fn set_tags(&mut self, tags: HashSet<WeatherTag>) {
         self.tags = tags;
     }
+
+    /* Add tags and day/night from keywords such as folder names ("rain", "night"), returning whether day/night was set */
+    pub fn add_keywords(&mut self, keywords: &[String]) -> bool {
+        let mut day_night_set = false;
+
+        for keyword in keywords {
+            match normalise(keyword).as_str() {
+                "day" | "daytime" | "noon" => self.is_day = Some(true),
+                "night" | "nighttime" | "midnight" | "moonlight" | "starry" => self.is_day = Some(false),
+                keyword => {
+                    self.tags.extend(WeatherTag::from_keyword(keyword));
+                    continue;
+                },
+            }
+
+            day_night_set = true;
+        }
+
+        day_night_set
+    }
 }
 
 /* Default weather */
@@ -64,6 +87,34 @@ impl Display for Weather {
     }
 }
 
+/* Day/night as written in sidecar and exported files, and given in control commands */
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DayNight {
+    Day,
+    Night,
+    Both,
+}
+
+impl DayNight {
+    pub fn from_is_day(is_day: Option<bool>) -> DayNight {
+        match is_day {
+            Some(true) => DayNight::Day,
+            Some(false) => DayNight::Night,
+            None => DayNight::Both,
+        }
+    }
+
+    pub fn is_day(&self) -> Option<bool> {
+        match self {
+            DayNight::Day => Some(true),
+            DayNight::Night => Some(false),
+            DayNight::Both => None,
+        }
+    }
+}
+
+
 /* Get current Weather status */
 pub fn get_current_weather() -> Result<Weather, Error> {
     let weather_data: WeatherData = weather_api::fetch_weather_data()?;
@@ -83,7 +134,7 @@ impl From<WeatherData> for Weather {
 }
 
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, EnumIter)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, EnumIter)]
 pub enum WeatherTag { 
     Sun,
     PartCloud,
@@ -107,11 +158,30 @@ impl WeatherTag {
         
         Ok(cond_map.get(&cond_text)
         .ok_or(format!("Could not find condition {cond_text} in weather_conditions.json"))?
-        .into_iter()
+        .iter()
         .cloned()
         .collect::<HashSet<WeatherTag>>())
     }
     
+    /* Match keyword against tag names, synonyms and weather_keywords.json, ignoring case and punctuation */
+    pub fn from_keyword(keyword: &str) -> HashSet<WeatherTag> {
+        let keyword = normalise(keyword);
+
+        let matched = WeatherTag::iter().find(|tag| 
+            normalise(&format!("{tag:?}")) == keyword
+                || tag.synonyms().iter().any(|synonym| normalise(synonym) == keyword)
+        );
+
+        match matched {
+            Some(tag) => HashSet::from([tag]),
+            None => KEYWORDS.get(&keyword)
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+        }
+    }
+
     /* Synonyms for outputting */
     pub fn synonyms(&self) -> Vec<String> {
         match self {
@@ -128,9 +198,35 @@ impl WeatherTag {
     }
 }
 
+/* Tag names (as saved) in a stable order */
+pub fn tag_names(tags: &HashSet<WeatherTag>) -> Vec<String> {
+    WeatherTag::iter()
+        .filter(|tag| tags.contains(tag))
+        .map(|tag| format!("{tag:?}"))
+        .collect()
+}
+
+/* Lowercase keyword and strip non-alphanumeric characters */
+fn normalise(keyword: &str) -> String {
+    keyword.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/* Keyword map parsed on first use (it is embedded, so only fails to parse if the build is broken) */
+static KEYWORDS: LazyLock<HashMap<String, Vec<WeatherTag>>> = LazyLock::new(|| load_keywords_map().unwrap_or_default());
+
+/* Load keywords (e.g. from filenames) mapped to tags from json file */
+fn load_keywords_map() -> std::io::Result<HashMap<String, Vec<WeatherTag>>> {
+    let contents = include_str!("weather_keywords.json");
+    let keywords: HashMap<String, Vec<WeatherTag>> = serde_json::from_str(contents)?;
+    Ok(keywords)
+}
+
 /* Load all conditions from json file */
 fn load_conditions_map() -> std::io::Result<HashMap<String, Vec<WeatherTag>>> {
     let contents = include_str!("weather_conditions.json");
-    let config: HashMap<String, Vec<WeatherTag>> = serde_json::from_str(&contents)?;
+    let config: HashMap<String, Vec<WeatherTag>> = serde_json::from_str(contents)?;
     Ok(config)
 }
\ No newline at end of file